@@ -1,7 +1,11 @@
 #version 140
 
 in vec3 v_normal;
+in vec3 v_tangent;
+in vec2 v_tex_coords;
 in vec3 v_position;
+in float v_tree_height;
+in float v_highlight; // 0 = None, 1 = Picked tree, 2 = Picked segment
 
 out vec4 color;
 
@@ -20,8 +24,18 @@ uniform vec3 u_material_diffuse;
 uniform vec3 u_material_specular;
 uniform float u_material_shininess;
 
+uniform bool u_has_diffuse_texture;
+uniform sampler2D u_diffuse_texture;
+uniform bool u_has_normal_texture;
+uniform sampler2D u_normal_texture;
+
+uniform bool u_foliage;
+uniform float u_alpha_cutoff;
+uniform float u_translucency;
+
 const vec3 light_color = vec3(1.0, 1.0, 1.0);
 const vec3 dark_color = vec3(0.0, 0.0, 0.0);
+const vec3 highlight_color = vec3(1.0, 0.6, 0.1);
 
 struct Material {
     vec3 ambient;
@@ -31,15 +45,16 @@ struct Material {
 };
 
 
+// Gradient from the base of the tree to its full-grown height, wherever the tree stands
 vec3 interpolate_color() {
-    float fragment_height = v_position.y;
-    float height_factor = clamp(v_position.y / u_total_height, 0.0, 1.0);
+    float height_factor = clamp(v_tree_height / u_total_height, 0.0, 1.0);
     return mix(u_interpolation_color_low, u_interpolation_color_high, height_factor);
 }
 
 Material get_material() {
+    Material material;
     if (u_color_mode == 0) {
-        return Material(
+        material = Material(
         u_material_ambient,
         u_material_diffuse,
         u_material_specular,
@@ -47,21 +62,56 @@ Material get_material() {
         );
     } else {
         vec3 object_color = interpolate_color();
-        return Material(
+        material = Material(
         object_color * 0.1,
         object_color,
         vec3(0.3, 0.3, 0.3),
         10.0
         );
     }
+
+    // The texture replaces the color of the material or the height gradient
+    if (u_has_diffuse_texture) {
+        vec3 texture_color = texture(u_diffuse_texture, v_tex_coords).rgb;
+        material.ambient = texture_color * 0.1;
+        material.diffuse = texture_color;
+    }
+    return material;
+}
+
+// Normal of the mesh, perturbed by the normal map if there is one
+vec3 surface_normal() {
+    vec3 normal = normalize(v_normal);
+    // Foliage is a single sheet lit from both sides, its back faces the other way
+    if (u_foliage && !gl_FrontFacing) {
+        normal = -normal;
+    }
+    // Meshes without texture coordinates have no tangents to orient the normal map by
+    if (!u_has_normal_texture || dot(v_tangent, v_tangent) < 1e-8) {
+        return normal;
+    }
+    vec3 tangent = normalize(v_tangent - normal * dot(normal, v_tangent));
+    vec3 bitangent = cross(normal, tangent);
+    vec3 mapped = texture(u_normal_texture, v_tex_coords).xyz * 2.0 - 1.0;
+    return normalize(mat3(tangent, bitangent, normal) * mapped);
 }
 
 void flat_shading(vec3 object_color) {
     color = vec4(object_color, 1.0);
 }
 
+// Light from behind foliage that shines through it
+float transmission(vec3 normal, vec3 light_dir) {
+    return u_foliage ? u_translucency * max(dot(-normal, light_dir), 0.0) : 0.0;
+}
+
 void gouraud_shading(vec3 object_color) {
-    float brightness = dot(normalize(v_normal), normalize(u_light_pos - v_position));
+    vec3 normal = surface_normal();
+    vec3 light_dir = normalize(u_light_pos - v_position);
+    float brightness = dot(normal, light_dir);
+    if (u_foliage) {
+        brightness = max(brightness, 0.0) + transmission(normal, light_dir);
+    }
     color = vec4(mix(dark_color, object_color, brightness), 1.0);
 }
 
@@ -71,7 +121,7 @@ void phong_shading(Material object_material) {
     vec3 ambient = object_material.ambient * light_color;
 
     // diffuse
-    vec3 norm = normalize(v_normal);
+    vec3 norm = surface_normal();
     vec3 light_dir = normalize(u_light_pos - v_position);
     float diff = max(dot(norm, light_dir), 0.0);
     vec3 diffuse = object_material.diffuse * diff * light_color;
@@ -82,13 +132,22 @@ void phong_shading(Material object_material) {
     float spec = pow(max(dot(norm, halfway_dir), 0.0), object_material.shininess);
     vec3 specular = object_material.specular * spec * light_color;
 
+    // translucency
+    vec3 transmitted = object_material.diffuse * transmission(norm, light_dir) * light_color;
+
     // combine
-    vec3 result = ambient + diffuse + specular;
+    vec3 result = ambient + diffuse + specular + transmitted;
     color = vec4(result, 1.0);
 }
 
 
 void main() {
+    // Cut the shape of leaf cards out of their texture
+    if (u_foliage && u_has_diffuse_texture
+        && texture(u_diffuse_texture, v_tex_coords).a < u_alpha_cutoff) {
+        discard;
+    }
+
     Material object_material = get_material();
 
     if (u_shading_mode == 0) {
@@ -98,5 +157,7 @@ void main() {
     } else {
         phong_shading(object_material);
     }
+
+    color.rgb = mix(color.rgb, highlight_color, 0.35 * v_highlight);
 }
 
@@ -2,20 +2,65 @@
 
 in vec3 position;
 in vec3 normal;
+in vec2 tex_coords;
+in vec3 tangent;
 in mat4 model_matrix;
+in float branch_order;
+in vec3 tree_origin;
+in float instance_scale;
+in float highlight;
 
 out vec3 v_normal;
+out vec3 v_tangent;
+out vec2 v_tex_coords;
 out vec3 v_position;
+// Height above the tree's origin, as if the tree were placed unscaled
+out float v_tree_height;
+out float v_highlight;
 
 uniform mat4 view;
 uniform mat4 projection;
 
+uniform float u_total_height;
+uniform float u_time;
+uniform vec2 u_wind_direction;
+uniform float u_wind_strength;
+uniform float u_wind_frequency;
+uniform float u_texture_tiling;
+uniform bool u_foliage;
+
+const float TWO_PI = 6.28318530718;
+
+// Horizontal displacement caused by wind, growing with height within the tree and branch order
+vec3 wind_offset(vec3 world_position) {
+    float height = max(world_position.y - tree_origin.y, 0.0) / max(u_total_height, 0.001);
+    float flexibility = height * height * (1.0 + 0.5 * branch_order);
+
+    // Offset the phase by tree position so that trees don't sway in unison
+    float phase = dot(tree_origin.xz, vec2(0.37, 0.71));
+    float gust = 0.5 + 0.5 * sin(u_time * u_wind_frequency * TWO_PI + phase);
+    float flutter = 0.1 * branch_order * sin(u_time * 7.0 + phase + height * 3.0);
+
+    float sway = u_wind_strength * flexibility * (0.5 + 0.5 * gust + flutter);
+    return vec3(u_wind_direction.x, 0.0, u_wind_direction.y) * sway;
+}
+
 void main() {
     mat3 normal_matrix = mat3(transpose(inverse(model_matrix)));
 
     vec4 world_position = model_matrix * vec4(position, 1.0);
+    v_tree_height = (world_position.y - tree_origin.y) / max(instance_scale, 0.001);
+    world_position.xyz += wind_offset(world_position.xyz);
     v_position = world_position.xyz;
     v_normal = normal_matrix * normal;
+    v_tangent = mat3(model_matrix) * tangent;
+
+    // Segments stretch the unit Y axis of the model to their length, repeat the texture
+    // along it instead of stretching it. Foliage shows its whole texture on every segment.
+    float segment_length = length(model_matrix[1].xyz);
+    float repeats = u_foliage ? 1.0 : segment_length * u_texture_tiling;
+    v_tex_coords = vec2(tex_coords.x, tex_coords.y * repeats);
+    v_highlight = highlight;
 
     gl_Position = projection * view * world_position;
 }
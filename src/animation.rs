@@ -0,0 +1,150 @@
+use crate::lsystem::GrowthFunction;
+
+/// Playback state of the growth animation, which replays the derivation of a plant
+/// from the axiom up to the configured number of iterations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrowthAnimation {
+    pub enabled: bool,
+    pub playing: bool,
+    /// Position on the timeline, measured in derivation steps
+    pub time: f32,
+    /// Playback speed in derivation steps per second
+    pub speed: f32,
+    /// Grow the plant as a timed L-system instead of interpolating discrete derivation steps
+    pub timed: bool,
+    pub growth_function: GrowthFunction,
+}
+
+impl Default for GrowthAnimation {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            playing: true,
+            time: 0.0,
+            speed: 0.5,
+            timed: false,
+            growth_function: GrowthFunction::default(),
+        }
+    }
+}
+
+impl GrowthAnimation {
+    /// Moves the timeline forward by `delta_seconds` of real time, pausing at the end
+    pub fn advance(&mut self, delta_seconds: f32, n_iterations: u32) {
+        #[allow(clippy::cast_precision_loss)]
+        let duration = n_iterations as f32;
+        self.time = self.time.clamp(0.0, duration);
+
+        if !self.enabled || !self.playing {
+            return;
+        }
+
+        self.time = (self.time + delta_seconds * self.speed).min(duration);
+        if self.time >= duration {
+            self.playing = false;
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.time = 0.0;
+        self.playing = true;
+    }
+
+    /// Returns the derivation step that should be displayed at the current time, and how far
+    /// the segments created in that step have grown (0 - not at all, 1 - full length)
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn step_and_progress(&self, n_iterations: u32) -> (u32, f32) {
+        if n_iterations == 0 {
+            return (0, 1.0);
+        }
+
+        #[allow(clippy::cast_precision_loss)]
+        let time = self.time.clamp(0.0, n_iterations as f32);
+        let step = (time.floor() as u32 + 1).min(n_iterations);
+        #[allow(clippy::cast_precision_loss)]
+        let progress = (time - (step - 1) as f32).clamp(0.0, 1.0);
+
+        (step, progress)
+    }
+
+    /// Time at which the timed L-system is evaluated for the current timeline position, where
+    /// one step of the timeline lasts `step_duration` (the smallest terminal age of the system).
+    /// Modules only start growing once they are created, so the system is evaluated almost one
+    /// step ahead - this lines the timeline up with the discrete mode and ends it with a fully
+    /// grown plant of the configured number of iterations.
+    pub fn timed_derivation_time(&self, step_duration: f32) -> f32 {
+        (self.time + 0.999) * step_duration
+    }
+}
+
+/// Maps the birth iteration of each symbol to the length of the segment it draws
+pub fn growth_lengths(births: &[u32], step: u32, progress: f32) -> Vec<f32> {
+    births
+        .iter()
+        .map(|&birth| if birth < step { 1.0 } else { progress })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lsystem::LSystem;
+
+    #[test]
+    fn test_step_and_progress() {
+        let mut animation = GrowthAnimation::default();
+
+        assert_eq!(animation.step_and_progress(0), (0, 1.0));
+        assert_eq!(animation.step_and_progress(3), (1, 0.0));
+
+        animation.time = 1.25;
+        assert_eq!(animation.step_and_progress(3), (2, 0.25));
+
+        animation.time = 3.0;
+        assert_eq!(animation.step_and_progress(3), (3, 1.0));
+    }
+
+    #[test]
+    fn test_advance_stops_at_end() {
+        let mut animation = GrowthAnimation {
+            enabled: true,
+            speed: 1.0,
+            ..GrowthAnimation::default()
+        };
+
+        animation.advance(1.5, 2);
+        assert!(animation.playing);
+        assert!((animation.time - 1.5).abs() < f32::EPSILON);
+
+        animation.advance(1.0, 2);
+        assert!(!animation.playing);
+        assert!((animation.time - 2.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_timed_derivation_ends_fully_grown() {
+        let lsystem = LSystem::new("F", [("F", "FF")]).with_terminal_ages([("F", 2.0)]);
+        let animation = GrowthAnimation {
+            time: 3.0,
+            ..GrowthAnimation::default()
+        };
+
+        let modules = lsystem
+            .derive_at(animation.timed_derivation_time(lsystem.min_terminal_age()))
+            .unwrap();
+        assert_eq!(modules.len(), 8);
+        assert!(
+            modules
+                .iter()
+                .all(|module| module.length(GrowthFunction::Linear) > 0.99)
+        );
+    }
+
+    #[test]
+    fn test_growth_lengths() {
+        assert_eq!(
+            growth_lengths(&[0, 1, 2, 2], 2, 0.5),
+            vec![1.0, 1.0, 0.5, 0.5]
+        );
+    }
+}
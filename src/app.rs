@@ -1,24 +1,42 @@
 use crate::gui::TreeGenerationConfig;
-use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::Instant;
 
 use glium::backend::glutin::SimpleWindowBuilder;
+use image::RgbaImage;
 use winit::{
     application::ApplicationHandler,
-    event::{DeviceId, ElementState, KeyEvent, WindowEvent},
+    event::{DeviceId, ElementState, KeyEvent, MouseButton, WindowEvent},
     event_loop::ActiveEventLoop,
     keyboard::{KeyCode, PhysicalKey},
     window::WindowId,
 };
 
 const DELTA_TIME: f32 = 0.1;
+/// Rotation of a dragged tree in radians per pixel of horizontal cursor movement
+const DRAG_ROTATION_SENSITIVITY: f64 = 0.01;
 
-use crate::common::ModelSelection;
-use crate::gui::LSystemConfig;
-use crate::lsystem::LSystem;
-use crate::model_loader::{load_floor, load_model};
-use crate::scene::Scene;
-use crate::turtle::TurtleInterpreter;
+use crate::culling::Aabb;
+use crate::export::{self, ExportSettings, ExportSource};
+use crate::generation::{GenerationJob, GrowthAnimationCache, GrowthJob, GrowthRequest};
+use crate::gui::{
+    EditTool, ExportRequest, LSystemConfig, SceneRequest, ScreenshotRequest, SpeciesConfig,
+};
+use crate::layout::{self, TreeLayout};
+use crate::lsystem::{GrowthLimits, GrowthStage};
+use crate::model_loader::load_floor;
+use crate::picking::{self, Inspection, Ray};
+use crate::placement::{self, DensityMap, PlacementArea, PlacementStrategy};
+use crate::scene::{Scene, Species};
+use crate::scene_file::SceneFile;
+use crate::screenshot;
+use crate::stats::PlantStats;
+use crate::symbol;
+use crate::terrain::{Terrain, TerrainConfig};
+use crate::validation::{self, Diagnostic};
 use crate::{
     camera::{FlyCamera, MovementDirection},
     renderer::Renderer,
@@ -31,16 +49,83 @@ pub enum AppInteractionMode {
     GuiInteraction,
 }
 
+/// Tree being moved or rotated with the mouse
+struct TreeDrag {
+    tree_index: usize,
+    last_cursor_x: f64,
+}
+
+/// Frame sequence being exported, one frame per redraw
+struct FrameExport {
+    settings: ExportSettings,
+    /// Camera at the start of the export, which the orbit starts from
+    camera: FlyCamera,
+    orbit_center: glm::Vec3,
+    next_frame: u32,
+}
+
+/// Growth animation of a species, whose frames are derived on a worker thread while the
+/// previous one is shown
+#[derive(Default)]
+struct SpeciesGrowth {
+    /// Lent to the job while there is one
+    cache: Option<GrowthAnimationCache>,
+    job: Option<GrowthJob>,
+    /// Request of the frame in the scene, with the error deriving it failed with
+    shown: Option<GrowthRequest>,
+    error: Option<String>,
+}
+
+/// Rule that created the inspected symbol, found again only when the symbol changes
+struct InspectedRule {
+    lsystem_config: LSystemConfig,
+    stage: GrowthStage,
+    symbol_index: usize,
+    rule: Result<Option<String>, String>,
+}
+
 #[derive(Default)]
 pub struct App {
     renderer: Option<Renderer>,
     camera: Option<FlyCamera>,
     pressed_keys: HashSet<KeyCode>,
+    /// Last known cursor position in physical pixels
+    cursor_position: Option<(f64, f64)>,
+    drag: Option<TreeDrag>,
+    /// Whether trees were placed by hand since they were last generated, in which case they
+    /// are only replaced when explicitly regenerated
+    layout_edited: bool,
     interaction_mode: AppInteractionMode,
-    lsystem_config: Option<LSystemConfig>,
+    /// Species the scene is brought to, some of which may still be generating
+    species_configs: Option<Vec<SpeciesConfig>>,
+    /// Config every species in the scene was generated from, behind `species_configs` while
+    /// species are being generated
+    scene_species_configs: Vec<SpeciesConfig>,
+    generation_jobs: Vec<GenerationJob>,
+    /// Species generated while other jobs were still running, with their indices
+    generated_species: Vec<(usize, Species)>,
+    /// Index among the species being generated of every species in the scene, `None` for
+    /// removed ones. Applied to the trees together with the species.
+    species_remapping: Option<Vec<Option<usize>>>,
+    /// Trees of a loaded scene, placed once its species are generated
+    pending_layout: Option<TreeLayout>,
     tree_generation_config: Option<TreeGenerationConfig>,
-    model_selection: Option<ModelSelection>,
+    terrain_config: Option<TerrainConfig>,
+    /// Density map used for placement, with the path it was loaded from
+    density_map: Option<(String, DensityMap)>,
     scene: Option<Scene>,
+    /// Growth animation of every species, in the same order as the species
+    species_growth: Vec<SpeciesGrowth>,
+    /// Species shown as variant B of the split view, the compared one with another grammar
+    comparison_config: Option<SpeciesConfig>,
+    comparison_job: Option<GenerationJob>,
+    /// Variant B of the split view with the index of the species it replaces
+    comparison_species: Option<(usize, Species)>,
+    /// Screenshot requested with the keyboard
+    screenshot_request: Option<ScreenshotRequest>,
+    frame_export: Option<FrameExport>,
+    last_frame_time: Option<Instant>,
+    inspected_rule: Option<InspectedRule>,
 }
 
 impl ApplicationHandler for App {
@@ -55,11 +140,8 @@ impl ApplicationHandler for App {
             self.renderer.as_ref().unwrap().get_aspect_ratio(),
         ));
         self.scene = Some(Scene::new(
-            load_floor(),
-            load_model(ModelSelection::default()),
-            Vec::new(),
-            Vec::new(),
-            3.0,
+            Terrain::flat(TerrainConfig::default().half_size),
+            load_floor().material,
             [10.0, 10.0, 10.0],
         ));
 
@@ -68,9 +150,9 @@ impl ApplicationHandler for App {
             .unwrap()
             .handle_interaction_mode_change(&self.interaction_mode);
 
-        self.lsystem_config = Some(self.get_current_lsystem_config().clone());
         self.tree_generation_config = Some(self.get_current_tree_generation_config().clone());
-        self.calculate_transformations(true);
+        self.update_species();
+        self.place_trees();
     }
 
     fn window_event(
@@ -98,27 +180,46 @@ impl ApplicationHandler for App {
                     return;
                 }
 
-                if self.requires_transformation_recalculation() {
-                    let new_lsystem_config = self.get_current_lsystem_config();
-                    log::info!("L-System config changed to {new_lsystem_config:?}");
-                    self.lsystem_config = Some(new_lsystem_config.clone());
+                self.update_terrain();
+                let weights_changed = self.update_species();
+                self.poll_generation();
+                self.update_comparison();
+                if weights_changed || self.requires_tree_placement() {
                     let new_tree_generation_config = self.get_current_tree_generation_config();
-
                     log::info!("Tree generation config changed to {new_tree_generation_config:?}");
                     self.tree_generation_config = Some(new_tree_generation_config.clone());
 
-                    // NOTE: this doesn't work without reordering transformation multiplication
-                    // self.calculate_transformations(self.requires_tree_regeneration());
-                    self.calculate_transformations(true);
+                    if self.layout_edited && !self.requires_tree_regeneration() {
+                        log::info!("Keeping trees edited by hand");
+                    } else {
+                        self.place_trees();
+                    }
                 }
-                self.update_fractal();
+                self.handle_scene_request();
+                self.update_growth_animation();
+                self.update_wind();
+                self.update_inspection();
 
                 self.render_scene();
+                self.handle_screenshot_request();
+                self.update_frame_export();
                 self.handle_movement();
             }
             WindowEvent::KeyboardInput { event, .. } => {
                 self.handle_key_event(&event);
             }
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some((position.x, position.y));
+                self.handle_drag();
+            }
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } => match state {
+                ElementState::Pressed => self.handle_click(),
+                ElementState::Released => self.drag = None,
+            },
             _ => {}
         }
     }
@@ -159,6 +260,9 @@ impl App {
             (ElementState::Pressed, PhysicalKey::Code(KeyCode::Escape)) => {
                 self.toggle_interaction_mode();
             }
+            (ElementState::Pressed, PhysicalKey::Code(KeyCode::F12)) => {
+                self.screenshot_request = Some(ScreenshotRequest::Window);
+            }
             (ElementState::Pressed, PhysicalKey::Code(code)) => {
                 self.pressed_keys.insert(code);
             }
@@ -169,6 +273,505 @@ impl App {
         }
     }
 
+    /// Whether variant B is generated and currently drawn in the right half of the window
+    fn shows_split_view(&self) -> bool {
+        self.comparison_species.is_some()
+            && self
+                .renderer
+                .as_ref()
+                .unwrap()
+                .get_gui_controller()
+                .shows_split_view()
+    }
+
+    /// Ray from the camera through the cursor, and whether the cursor is over variant B in the
+    /// right half of the split view
+    #[allow(clippy::cast_possible_truncation)]
+    fn cursor_ray(&self) -> Option<(Ray, bool)> {
+        let (mut x, y) = self.cursor_position?;
+        let (mut width, height) = self.renderer.as_ref().unwrap().window_size();
+        let camera = self.camera.as_ref().unwrap();
+
+        // Each half of the split view has its own projection, as in `render_scene`
+        let split_view = self.shows_split_view();
+        let half_width = width / 2;
+        let in_comparison = split_view && x >= f64::from(half_width);
+        if in_comparison {
+            x -= f64::from(half_width);
+            width -= half_width;
+        } else if split_view {
+            width = half_width;
+        }
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let ndc = (
+            (2.0 * x / f64::from(width) - 1.0) as f32,
+            (1.0 - 2.0 * y / f64::from(height)) as f32,
+        );
+        let view_parameters = if split_view {
+            camera.split_view_parameters()
+        } else {
+            camera.view_parameters()
+        };
+        let view_projection = glm::Mat4::from(view_parameters.projection_matrix)
+            * glm::Mat4::from(view_parameters.view_matrix);
+        let ray = Ray::from_screen(
+            ndc,
+            &view_projection,
+            &glm::Vec3::from(view_parameters.camera_position),
+        );
+        Some((ray, in_comparison))
+    }
+
+    /// Applies the edit tool selected in the GUI at the cursor
+    fn handle_click(&mut self) {
+        if self.interaction_mode != AppInteractionMode::GuiInteraction {
+            return;
+        }
+        let gui = self.renderer.as_ref().unwrap().get_gui_controller();
+        if gui.wants_pointer_input() {
+            return;
+        }
+        let (edit_tool, selected_species) = (gui.get_edit_tool(), gui.get_selected_species());
+        let Some((ray, in_comparison)) = self.cursor_ray() else {
+            return;
+        };
+        // Clicks on variant B hit the trees as they are drawn there
+        let species_override = self
+            .comparison_species
+            .as_ref()
+            .filter(|_| in_comparison)
+            .map(|(species_index, species)| (*species_index, species));
+
+        let scene = self.scene.as_mut().unwrap();
+        match edit_tool {
+            EditTool::Inspect => {
+                let selection =
+                    picking::pick_segment(scene, &ray, species_override).filter(|selection| {
+                        // Selected segments refer to the species in the scene, not to variant B
+                        species_override.is_none_or(|(species_index, _)| {
+                            scene.tree_species_indices().get(selection.tree_index)
+                                != Some(&species_index)
+                        })
+                    });
+                log::info!("Picked {selection:?}");
+                scene.set_selection(selection);
+            }
+            EditTool::Plant => {
+                if let Some(point) = ray.intersect_terrain(scene.terrain()) {
+                    log::info!("Planting species {selected_species} at {point:?}");
+                    scene.add_tree(glm::translation(&point), selected_species);
+                    self.set_layout_edited(true);
+                }
+            }
+            EditTool::Move | EditTool::Rotate => {
+                self.drag = picking::pick_segment(scene, &ray, species_override)
+                    .zip(self.cursor_position)
+                    .map(|(selection, (x, _))| TreeDrag {
+                        tree_index: selection.tree_index,
+                        last_cursor_x: x,
+                    });
+            }
+            EditTool::Delete => {
+                if let Some(selection) = picking::pick_segment(scene, &ray, species_override) {
+                    log::info!("Removing tree {}", selection.tree_index);
+                    scene.remove_tree(selection.tree_index);
+                    self.set_layout_edited(true);
+                }
+            }
+        }
+    }
+
+    /// Moves or rotates the dragged tree to follow the cursor
+    #[allow(clippy::cast_possible_truncation)]
+    fn handle_drag(&mut self) {
+        let (Some(drag), Some((cursor_x, _))) = (&self.drag, self.cursor_position) else {
+            return;
+        };
+        let tree_index = drag.tree_index;
+        let edit_tool = self
+            .renderer
+            .as_ref()
+            .unwrap()
+            .get_gui_controller()
+            .get_edit_tool();
+        let ray = self.cursor_ray().map(|(ray, _)| ray);
+
+        let scene = self.scene.as_mut().unwrap();
+        let Some(mut displacement_matrix) = scene.displacement_matrices.get(tree_index).copied()
+        else {
+            self.drag = None;
+            return;
+        };
+        match edit_tool {
+            EditTool::Move => {
+                let Some(point) = ray.and_then(|ray| ray.intersect_terrain(scene.terrain())) else {
+                    return;
+                };
+                displacement_matrix.set_column(3, &glm::vec4(point.x, point.y, point.z, 1.0));
+            }
+            EditTool::Rotate => {
+                let angle = (cursor_x - drag.last_cursor_x) * DRAG_ROTATION_SENSITIVITY;
+                displacement_matrix *= glm::rotation(angle as f32, &glm::vec3(0.0, 1.0, 0.0));
+            }
+            EditTool::Inspect | EditTool::Plant | EditTool::Delete => return,
+        }
+
+        scene.set_displacement_matrix(tree_index, displacement_matrix);
+        self.drag = Some(TreeDrag {
+            tree_index,
+            last_cursor_x: cursor_x,
+        });
+        self.set_layout_edited(true);
+    }
+
+    fn set_layout_edited(&mut self, edited: bool) {
+        self.layout_edited = edited;
+        self.renderer.as_mut().unwrap().set_layout_edited(edited);
+    }
+
+    /// Saves or loads the species and trees if requested in the GUI
+    fn handle_scene_request(&mut self) {
+        let renderer = self.renderer.as_mut().unwrap();
+        let Some(request) = renderer.take_scene_request() else {
+            return;
+        };
+        let path = renderer.get_gui_controller().get_scene_path().to_string();
+
+        let status = match request {
+            SceneRequest::Save => {
+                let scene_file = SceneFile {
+                    species: self.applied_species().to_vec(),
+                    layout: self.target_layout(),
+                };
+                let message = format!(
+                    "Saved {} species and {} trees to {path}",
+                    scene_file.species.len(),
+                    scene_file.layout.displacement_matrices.len()
+                );
+                scene_file.save(&path).map(|()| message)
+            }
+            SceneRequest::Load => SceneFile::load(&path).map(|scene_file| {
+                let message = format!(
+                    "Loaded {} species and {} trees from {path}",
+                    scene_file.species.len(),
+                    scene_file.layout.displacement_matrices.len()
+                );
+                self.load_scene(scene_file);
+                message
+            }),
+        };
+
+        match &status {
+            Ok(message) => {
+                log::info!("{message}");
+                if request == SceneRequest::Load {
+                    self.set_layout_edited(true);
+                }
+            }
+            Err(e) => log::warn!("{e}"),
+        }
+        self.renderer.as_mut().unwrap().set_scene_status(status);
+    }
+
+    /// Trees as they are once the species being generated are in the scene, with the indices
+    /// of the applied species
+    fn target_layout(&self) -> TreeLayout {
+        if let Some(layout) = &self.pending_layout {
+            return layout.clone();
+        }
+        let mut layout = self.scene.as_ref().unwrap().layout();
+        if let Some(remapping) = &self.species_remapping {
+            layout.remap_species(remapping);
+        }
+        layout
+    }
+
+    /// Brings the species of the loaded scene into the GUI. Its trees replace the current ones
+    /// together with the species, right away if they are already in the scene.
+    fn load_scene(&mut self, scene_file: SceneFile) {
+        self.species_remapping = None;
+        if scene_file.species == self.applied_species() && self.generation_jobs.is_empty() {
+            self.pending_layout = None;
+            self.scene.as_mut().unwrap().set_trees(scene_file.layout);
+        } else {
+            self.pending_layout = Some(scene_file.layout);
+        }
+        self.renderer
+            .as_mut()
+            .unwrap()
+            .set_species(scene_file.species);
+    }
+
+    /// Saves a screenshot if requested with the keyboard or in the GUI. Window screenshots with
+    /// the GUI are read back from the frame just shown, the others are rendered anew without it.
+    fn handle_screenshot_request(&mut self) {
+        let renderer = self.renderer.as_mut().unwrap();
+        let Some(request) = self
+            .screenshot_request
+            .take()
+            .or_else(|| renderer.take_screenshot_request())
+        else {
+            return;
+        };
+        let settings = renderer
+            .get_gui_controller()
+            .get_screenshot_settings()
+            .clone();
+        let time = renderer.elapsed_time();
+        let camera = self.camera.clone().unwrap();
+
+        let image = match request {
+            ScreenshotRequest::Window if settings.include_gui => renderer.read_window(),
+            ScreenshotRequest::Window => {
+                let size = renderer.window_size();
+                self.render_image(&camera, size, time)
+            }
+            ScreenshotRequest::HighResolution => {
+                self.render_image(&camera, (settings.width, settings.height), time)
+            }
+        };
+        let status = image.and_then(|image| {
+            let path = screenshot::next_free_path(Path::new(&settings.directory), "screenshot");
+            screenshot::save_png(&image, &path)?;
+            Ok(format!("Saved {}", path.display()))
+        });
+
+        match &status {
+            Ok(message) => log::info!("{message}"),
+            Err(e) => log::warn!("{e}"),
+        }
+        self.renderer
+            .as_mut()
+            .unwrap()
+            .set_screenshot_status(status);
+    }
+
+    /// Starts or cancels exporting a frame sequence if requested in the GUI, then exports the
+    /// next frame. Frames are rendered at their own time rather than the time of the display.
+    fn update_frame_export(&mut self) {
+        let renderer = self.renderer.as_mut().unwrap();
+        match renderer.take_export_request() {
+            Some(ExportRequest::Start) => {
+                let settings = renderer.get_gui_controller().get_export_settings().clone();
+                if let Err(e) = export::clear_frames(Path::new(&settings.directory)) {
+                    self.finish_frame_export(Err(e));
+                    return;
+                }
+                renderer.set_export_progress(Some((0, settings.frames)));
+                self.frame_export = Some(FrameExport {
+                    settings,
+                    camera: self.camera.clone().unwrap(),
+                    orbit_center: self.scene_center(),
+                    next_frame: 0,
+                });
+                // The growth animation is brought to the first frame before it is rendered
+                return;
+            }
+            Some(ExportRequest::Cancel) => {
+                if let Some(export) = self.frame_export.take() {
+                    self.finish_frame_export(Ok(format!(
+                        "Export cancelled after {} frames",
+                        export.next_frame
+                    )));
+                }
+                return;
+            }
+            None => {}
+        }
+        let Some(mut export) = self.frame_export.take() else {
+            return;
+        };
+        // Growth frames are rendered once the plants have grown to their time
+        if export.settings.source == ExportSource::Growth && self.growth_pending() {
+            self.frame_export = Some(export);
+            return;
+        }
+
+        let frame = export.next_frame;
+        let settings = &export.settings;
+        let mut camera = export.camera.clone();
+        if settings.source == ExportSource::Orbit {
+            let position = export::orbit_position(
+                &export.orbit_center,
+                &export.camera.position(),
+                settings.orbit_angle(frame),
+            );
+            camera.look_at(position, &export.orbit_center);
+        }
+        let lod_settings = self
+            .renderer
+            .as_ref()
+            .unwrap()
+            .get_gui_controller()
+            .get_lod_settings()
+            .clone();
+        self.scene
+            .as_mut()
+            .unwrap()
+            .update_lod_selection(&camera.position(), &lod_settings);
+
+        let path = export::frame_path(Path::new(&settings.directory), frame);
+        let result = self
+            .render_image(
+                &camera,
+                (settings.width, settings.height),
+                settings.frame_time(frame),
+            )
+            .and_then(|image| screenshot::save_png(&image, &path));
+        if let Err(e) = result {
+            self.finish_frame_export(Err(e));
+            return;
+        }
+
+        export.next_frame += 1;
+        if export.next_frame < settings.frames {
+            self.renderer
+                .as_mut()
+                .unwrap()
+                .set_export_progress(Some((export.next_frame, settings.frames)));
+            self.frame_export = Some(export);
+        } else {
+            self.finish_frame_export(Ok(format!(
+                "Exported {} frames to {}",
+                settings.frames, settings.directory
+            )));
+        }
+    }
+
+    fn finish_frame_export(&mut self, status: Result<String, String>) {
+        match &status {
+            Ok(message) => log::info!("{message}"),
+            Err(e) => log::warn!("{e}"),
+        }
+        let renderer = self.renderer.as_mut().unwrap();
+        renderer.set_export_progress(None);
+        renderer.set_export_status(status);
+    }
+
+    /// Center of the box around all trees, or the origin if there are none
+    fn scene_center(&self) -> glm::Vec3 {
+        let mut bounds = Aabb::empty();
+        for bounding_box in self.scene.as_ref().unwrap().bounding_boxes() {
+            bounds.union(bounding_box);
+        }
+        if bounds.is_empty() {
+            glm::Vec3::zeros()
+        } else {
+            (bounds.min + bounds.max) / 2.0
+        }
+    }
+
+    /// Renders the scene as seen by the camera into an image, split like the window if variant B
+    /// of the split view is shown
+    #[allow(clippy::cast_precision_loss)]
+    fn render_image(
+        &mut self,
+        camera: &FlyCamera,
+        (width, height): (u32, u32),
+        time: f32,
+    ) -> Result<RgbaImage, String> {
+        if width == 0 || height == 0 {
+            return Err("Cannot render an empty image".to_string());
+        }
+        let renderer = self.renderer.as_mut().unwrap();
+        let scene = self.scene.as_ref().unwrap();
+
+        let shows_split_view = renderer.get_gui_controller().shows_split_view();
+        let Some((species_index, species)) = self
+            .comparison_species
+            .as_ref()
+            .filter(|_| shows_split_view)
+        else {
+            let view_parameters =
+                camera.view_parameters_with_aspect_ratio(width as f32 / height as f32);
+            return renderer.render_image(scene, &view_parameters, None, (width, height), time);
+        };
+        let half_width = width / 2;
+        let view_parameters =
+            camera.view_parameters_with_aspect_ratio(half_width.max(1) as f32 / height as f32);
+        let left =
+            renderer.render_image(scene, &view_parameters, None, (half_width, height), time)?;
+        let right = renderer.render_image(
+            scene,
+            &view_parameters,
+            Some((*species_index, species)),
+            (width - half_width, height),
+            time,
+        )?;
+        Ok(screenshot::side_by_side(&left, &right))
+    }
+
+    /// Passes details of the selected segment to the inspector, dropping the selection if the
+    /// segment no longer exists
+    fn update_inspection(&mut self) {
+        let limits = self.get_growth_limits();
+        let scene = self.scene.as_mut().unwrap();
+        let Some(selection) = scene.selection().copied() else {
+            self.renderer.as_mut().unwrap().set_inspection(None);
+            return;
+        };
+
+        let species_index = scene
+            .tree_species_indices()
+            .get(selection.tree_index)
+            .copied();
+        // The segments in the scene refer to the alphabet of the grammar they were derived with
+        let species_config = species_index.and_then(|index| self.scene_species_configs.get(index));
+        let inspected_rule = &mut self.inspected_rule;
+        let inspection = scene
+            .tree_species(selection.tree_index)
+            .and_then(|species| {
+                let segment = species
+                    .segments(selection.level)
+                    .get(selection.segment_index)?;
+                Some((segment, species.level_stage(selection.level)?))
+            })
+            .zip(species_config)
+            .and_then(|((segment, stage), config)| {
+                let lsystem = config.lsystem_config.to_lsystem().with_limits(limits);
+                let symbol_name = lsystem.alphabet().get_name(segment.symbol)?;
+                if inspected_rule.as_ref().is_none_or(|inspected| {
+                    inspected.lsystem_config != config.lsystem_config
+                        || inspected.stage != stage
+                        || inspected.symbol_index != segment.symbol_index
+                }) {
+                    let rule = lsystem
+                        .rule_of_symbol(stage, segment.symbol_index)
+                        .map(|rule| {
+                            rule.map(|rule| {
+                                let (predecessor, successor) =
+                                    &config.lsystem_config.production_rules[rule];
+                                format!("{rule}: {predecessor} -> {successor}")
+                            })
+                        });
+                    *inspected_rule = Some(InspectedRule {
+                        lsystem_config: config.lsystem_config.clone(),
+                        stage,
+                        symbol_index: segment.symbol_index,
+                        rule,
+                    });
+                }
+
+                Some(Inspection {
+                    tree_index: selection.tree_index,
+                    species_name: config.name.clone(),
+                    displacement_matrix: scene.displacement_matrices[selection.tree_index],
+                    segment_index: selection.segment_index,
+                    segment: segment.clone(),
+                    symbol_name: symbol::written(symbol_name).into_owned(),
+                    rule: inspected_rule.as_ref().unwrap().rule.clone(),
+                })
+            });
+
+        if inspection.is_none() {
+            scene.set_selection(None);
+        }
+        self.renderer.as_mut().unwrap().set_inspection(inspection);
+    }
+
     fn handle_movement(&mut self) {
         if self.interaction_mode != AppInteractionMode::CameraControl {
             return;
@@ -211,136 +814,578 @@ impl App {
         let renderer = self.renderer.as_mut().unwrap();
         let camera = self.camera.as_ref().unwrap();
 
+        self.scene.as_mut().unwrap().update_lod_selection(
+            &camera.position(),
+            renderer.get_gui_controller().get_lod_settings(),
+        );
+
+        let comparison = self
+            .comparison_species
+            .as_ref()
+            .filter(|_| renderer.get_gui_controller().shows_split_view())
+            .map(|(species_index, species)| (*species_index, species));
+        let view_parameters = if comparison.is_some() {
+            camera.split_view_parameters()
+        } else {
+            camera.view_parameters()
+        };
         renderer.render_scene(
             self.scene.as_ref().unwrap(),
             &self.interaction_mode,
-            &camera.view_parameters(),
+            &view_parameters,
+            comparison,
         );
     }
 
-    /// Generates N matrices with random transalations within the configured bounds
-    #[allow(clippy::cast_precision_loss)]
-    fn generate_displacement_matrices(&self) -> Vec<glm::Mat4> {
-        let num_trees = self.get_current_tree_generation_config().get_num_trees();
-        let mut rng = rand::rng();
+    /// Replaces the trees in the scene with newly placed ones, together with the species if
+    /// some are still being generated
+    fn place_trees(&mut self) {
+        // The new trees already refer to the species being generated
+        self.species_remapping = None;
+        let layout = self.generate_trees();
+        if self.generation_jobs.is_empty() {
+            self.pending_layout = None;
+            self.scene.as_mut().unwrap().set_trees(layout);
+        } else {
+            self.pending_layout = Some(layout);
+        }
+        self.renderer
+            .as_mut()
+            .unwrap()
+            .unset_requires_tree_regeneration();
+        self.set_layout_edited(false);
+    }
 
-        let tree_generation_config = self.get_current_tree_generation_config();
+    /// Generates trees placed by the configured strategy, deterministic for a given seed
+    #[allow(clippy::cast_precision_loss)]
+    fn generate_trees(&mut self) -> TreeLayout {
+        let tree_generation_config = self.get_current_tree_generation_config().clone();
+        let mut rng = StdRng::seed_from_u64(tree_generation_config.get_seed());
 
         let (xmin, xmax) = tree_generation_config.get_x_bounds();
         let (zmin, zmax) = tree_generation_config.get_z_bounds();
+        let area = PlacementArea {
+            min: glm::vec2(xmin as f32, zmin as f32),
+            max: glm::vec2(xmax as f32, zmax as f32),
+        };
+        let num_trees = usize::from(tree_generation_config.get_num_trees());
 
-        (0..num_trees)
-            .map(|_| {
-                let x = rng.random_range(xmin..xmax) as f32;
-                let z = rng.random_range(zmin..zmax) as f32;
-                let y_rotation = rng.random_range(0.0..360.0_f32).to_radians();
+        let positions = match tree_generation_config.get_placement_strategy() {
+            PlacementStrategy::Uniform => placement::uniform(&mut rng, &area, num_trees),
+            PlacementStrategy::PoissonDisk => placement::poisson_disk(
+                &mut rng,
+                &area,
+                num_trees,
+                tree_generation_config.get_min_spacing(),
+            ),
+            PlacementStrategy::JitteredGrid => placement::jittered_grid(&mut rng, &area, num_trees),
+            PlacementStrategy::DensityMap => {
+                match self.load_density_map(tree_generation_config.get_density_map_path()) {
+                    Some(density_map) => {
+                        placement::density_mapped(&mut rng, &area, num_trees, density_map)
+                    }
+                    None => placement::uniform(&mut rng, &area, num_trees),
+                }
+            }
+        };
+        if positions.len() < num_trees {
+            log::info!(
+                "Placed {} of {num_trees} trees, the area cannot fit more",
+                positions.len()
+            );
+        }
+
+        let variation = tree_generation_config.get_variation();
+        let terrain = self.scene.as_ref().unwrap().terrain();
+        let (displacement_matrices, iteration_offsets): (Vec<glm::Mat4>, Vec<u32>) = positions
+            .into_iter()
+            .map(|position| {
+                let (x, z) = (position.x, position.y);
+                let (local_transformation, iteration_offset) = variation.sample(&mut rng);
 
-                glm::translation(&glm::vec3(x, 0.0, z))
-                    * glm::rotation(y_rotation, &glm::vec3(0.0, 1.0, 0.0))
+                (
+                    glm::translation(&glm::vec3(x, terrain.height_at(x, z), z))
+                        * local_transformation,
+                    iteration_offset,
+                )
             })
-            .collect::<Vec<glm::Mat4>>()
+            .unzip();
+
+        let weights: Vec<f32> = self
+            .applied_species()
+            .iter()
+            .map(|species| species.weight)
+            .collect();
+        let tree_species =
+            placement::assign_species(&mut rng, &weights, displacement_matrices.len());
+
+        TreeLayout {
+            displacement_matrices,
+            tree_species,
+            iteration_offsets,
+        }
     }
 
-    fn update_fractal(&mut self) {
-        let Some(renderer) = &self.renderer else {
+    /// Returns the density map at `path`, reloading it only when the path changes.
+    /// Failures are reported in the GUI and yield `None`.
+    fn load_density_map(&mut self, path: &str) -> Option<&DensityMap> {
+        let cached = self
+            .density_map
+            .as_ref()
+            .is_some_and(|(cached_path, _)| cached_path == path);
+        if !cached {
+            self.density_map = None;
+            match DensityMap::load(path) {
+                Ok(density_map) => self.density_map = Some((path.to_string(), density_map)),
+                Err(e) => {
+                    log::warn!("{e}, falling back to uniform placement");
+                    self.renderer.as_mut().unwrap().set_placement_error(Some(e));
+                    return None;
+                }
+            }
+        }
+
+        self.renderer.as_mut().unwrap().set_placement_error(None);
+        self.density_map
+            .as_ref()
+            .map(|(_, density_map)| density_map)
+    }
+
+    fn update_terrain(&mut self) {
+        let renderer = self.renderer.as_mut().unwrap();
+        let config = renderer.get_gui_controller().get_terrain_config();
+        if self.terrain_config.as_ref() == Some(config) {
             return;
-        };
+        }
 
-        let gui = renderer.get_gui_controller();
-        let config = gui.get_lsystem_config();
-        let model_selection = gui.get_model_selection();
+        log::info!("Terrain config changed to {config:?}");
+        self.terrain_config = Some(config.clone());
 
-        let config_changed = self.lsystem_config.as_ref() != Some(config);
-        let model_changed = self.model_selection.as_ref() != Some(model_selection);
+        match Terrain::from_config(config) {
+            Ok(terrain) => {
+                self.scene.as_mut().unwrap().set_terrain(terrain);
+                renderer.set_terrain_error(None);
+            }
+            Err(e) => {
+                log::warn!("{e}");
+                renderer.set_terrain_error(Some(e));
+            }
+        }
+    }
 
-        if !config_changed && !model_changed {
-            return;
+    /// Brings the species in the scene up to date with the GUI, regenerating only those whose
+    /// grammar or base model changed on a worker thread. Returns true if the species weights
+    /// changed, in which case trees have to be distributed among species anew.
+    ///
+    /// Nothing is applied while any species has grammar errors or grows beyond the limits, so
+    /// that the last valid plants stay in the scene.
+    fn update_species(&mut self) -> bool {
+        let configs = self.get_current_species().to_vec();
+        let limits = self.get_growth_limits();
+        if !configs
+            .iter()
+            .all(|config| Self::can_generate(config, limits))
+        {
+            return false;
+        }
+        let previous = self.species_configs.take().unwrap_or_default();
+        if configs == previous {
+            self.species_configs = Some(previous);
+            return false;
         }
 
-        log::info!(
-            "Updating fractal - config_changed: {config_changed}, model_changed: {model_changed}"
-        );
+        log::info!("Species changed to {configs:?}");
+        // Trees keep their species when others are added or removed
+        let remapping = layout::remapping(&previous, &configs);
+        self.species_remapping = Some(match self.species_remapping.take() {
+            Some(pending) => pending
+                .iter()
+                .map(|index| index.and_then(|index| remapping[index]))
+                .collect(),
+            None => remapping,
+        });
+        for (species_index, config) in configs.iter().enumerate() {
+            if previous
+                .get(species_index)
+                .is_none_or(|previous_config| config.requires_regeneration(previous_config))
+            {
+                self.start_generation(species_index, config.clone(), limits);
+            }
+        }
+        // Dropping the jobs of removed species cancels them
+        self.generation_jobs
+            .retain(|job| job.species_index() < configs.len());
+        self.generated_species
+            .retain(|(species_index, _)| *species_index < configs.len());
 
-        if config_changed {
-            log::info!("L-System config changed to {config:?}");
-            self.lsystem_config = Some(config.clone());
+        let weights_changed = !configs
+            .iter()
+            .map(|config| config.weight)
+            .eq(previous.iter().map(|config| config.weight));
+        self.species_configs = Some(configs);
+        if self.generation_jobs.is_empty() {
+            self.apply_generated_species();
+        } else {
+            self.update_species_colors();
         }
+        weights_changed
+    }
 
-        if model_changed {
-            log::info!("Model selection changed to {model_selection:?}");
-            self.model_selection = Some(*model_selection);
+    /// Returns false if the grammar of the species has errors or grows beyond the limits
+    fn can_generate(config: &SpeciesConfig, limits: GrowthLimits) -> bool {
+        !validation::validate(&config.lsystem_config)
+            .iter()
+            .any(Diagnostic::is_error)
+            && config
+                .lsystem_config
+                .to_lsystem()
+                .with_limits(limits)
+                .check_growth(config.lsystem_config.n_iterations)
+                .is_ok()
+    }
 
-            let new_base = load_model(*model_selection);
+    /// Generates variant B of the split view in the background whenever its grammar or the
+    /// species it is compared with change, keeping the previous one until it is done
+    fn update_comparison(&mut self) {
+        let gui = self.renderer.as_ref().unwrap().get_gui_controller();
+        let Some((species_index, lsystem_config)) = gui
+            .get_comparison()
+            .map(|(species_index, lsystem_config)| (species_index, lsystem_config.clone()))
+        else {
+            self.comparison_config = None;
+            self.comparison_job = None;
+            self.comparison_species = None;
+            return;
+        };
+        let limits = gui.get_growth_limits();
+        let parallel_rewriting = gui.get_parallel_rewriting();
 
-            if let Some(scene) = &mut self.scene {
-                scene.set_fractal_base(new_base);
+        if let Some(compared) = self.applied_species().get(species_index) {
+            let config = SpeciesConfig {
+                lsystem_config,
+                ..compared.clone()
+            };
+            if self.comparison_config.as_ref() != Some(&config)
+                && Self::can_generate(&config, limits)
+            {
+                // Another species is compared, the previous variant B does not apply to it
+                if self
+                    .comparison_species
+                    .as_ref()
+                    .is_some_and(|(index, _)| *index != species_index)
+                {
+                    self.comparison_species = None;
+                }
+                self.comparison_job = Some(GenerationJob::spawn(
+                    species_index,
+                    config.clone(),
+                    limits,
+                    parallel_rewriting,
+                ));
+                self.comparison_config = Some(config);
             }
         }
 
-        let production_rules: HashMap<char, String> =
-            config.production_rules.iter().cloned().collect();
-        let lsystem = LSystem::new(&config.axiom, production_rules);
-        let generated = lsystem.generate(config.n_iterations);
-        let transformations = TurtleInterpreter::interpret(&generated, config.angle);
+        if let Some(job) = &self.comparison_job
+            && let Some(result) = job.try_finish()
+        {
+            let species_index = job.species_index();
+            self.comparison_job = None;
+            match result {
+                Ok(species) => self.comparison_species = Some((species_index, species)),
+                Err(e) => log::warn!("Failed to generate variant B: {e}"),
+            }
+        }
+    }
 
-        let num_trees = self.get_current_tree_generation_config().get_num_trees();
-        let transforms_per_instance = transformations.len() / num_trees as usize;
-        let transformations = transformations
-            .chunks(transforms_per_instance)
-            .map(<[_]>::to_vec)
-            .collect::<Vec<Vec<glm::Mat4>>>();
+    /// Generates the species in the background, replacing the job currently generating it
+    fn start_generation(
+        &mut self,
+        species_index: usize,
+        config: SpeciesConfig,
+        limits: GrowthLimits,
+    ) {
+        let parallel_rewriting = self
+            .renderer
+            .as_ref()
+            .unwrap()
+            .get_gui_controller()
+            .get_parallel_rewriting();
+        self.generation_jobs
+            .retain(|job| job.species_index() != species_index);
+        self.generated_species
+            .retain(|(index, _)| *index != species_index);
+        self.generation_jobs.push(GenerationJob::spawn(
+            species_index,
+            config,
+            limits,
+            parallel_rewriting,
+        ));
+    }
+
+    /// Collects finished species. The previous ones stay in the scene until all jobs are done,
+    /// so that species and trees change at once.
+    #[allow(clippy::cast_precision_loss)]
+    fn poll_generation(&mut self) {
+        if self.generation_jobs.is_empty() {
+            return;
+        }
+
+        let mut finished = Vec::new();
+        self.generation_jobs.retain(|job| match job.try_finish() {
+            Some(result) => {
+                finished.push((job.species_index(), result));
+                false
+            }
+            None => true,
+        });
+        for (species_index, result) in finished {
+            match result {
+                Ok(species) => self.generated_species.push((species_index, species)),
+                Err(e) => log::warn!("Failed to generate species {species_index}: {e}"),
+            }
+        }
+
+        let progress = (!self.generation_jobs.is_empty()).then(|| {
+            self.generation_jobs
+                .iter()
+                .map(GenerationJob::progress)
+                .sum::<f32>()
+                / self.generation_jobs.len() as f32
+        });
+        self.renderer
+            .as_mut()
+            .unwrap()
+            .set_generation_progress(progress);
+
+        if self.generation_jobs.is_empty() {
+            self.apply_generated_species();
+        }
+    }
+
+    /// Brings the species in the scene up to date with the generated ones
+    fn apply_generated_species(&mut self) {
+        let configs = self.species_configs.as_deref().unwrap_or_default();
+        let scene = self.scene.as_mut().unwrap();
 
-        if let Some(scene) = &mut self.scene {
-            scene.update_transformations(transformations, config.fractal_height);
+        self.generated_species
+            .sort_by_key(|(species_index, _)| *species_index);
+        for (species_index, species) in self.generated_species.drain(..) {
+            // Species are appended in order, one that failed to generate leaves a gap
+            if species_index <= scene.species().len() {
+                scene.set_species(species_index, species);
+                let config = configs[species_index].clone();
+                if species_index < self.scene_species_configs.len() {
+                    self.scene_species_configs[species_index] = config;
+                } else {
+                    self.scene_species_configs.push(config);
+                }
+            }
         }
+        scene.truncate_species(configs.len());
+        self.scene_species_configs.truncate(configs.len());
+        if let Some(layout) = self.pending_layout.take() {
+            scene.set_trees(layout);
+        } else if let Some(remapping) = &self.species_remapping {
+            scene.remap_tree_species(remapping);
+        }
+        self.species_remapping = None;
+        // The new species are fully grown until their next frame of the growth animation
+        for growth in &mut self.species_growth {
+            growth.shown = None;
+        }
+        self.update_species_colors();
+        self.update_plant_stats();
+    }
 
-        renderer.request_redraw();
+    fn update_plant_stats(&mut self) {
+        let configs = &self.scene_species_configs;
+        let scene = self.scene.as_ref().unwrap();
+        let plant_stats = configs
+            .iter()
+            .zip(scene.species())
+            .map(|(config, species)| {
+                let lsystem_config = &config.lsystem_config;
+                let derivation_length = lsystem_config
+                    .to_lsystem()
+                    .estimate_growth(lsystem_config.n_iterations)
+                    .last()
+                    .map_or(0, |estimate| estimate.length);
+                PlantStats::new(species, derivation_length)
+            })
+            .collect();
+        self.renderer.as_mut().unwrap().set_plant_stats(plant_stats);
     }
 
-    fn calculate_transformations(&mut self, rerandomize_positions: bool) {
-        let lsystem_config = self.get_current_lsystem_config();
-        let target_height = lsystem_config.fractal_height;
-        let production_rules: HashMap<char, String> =
-            lsystem_config.production_rules.iter().cloned().collect();
-        let lsystem = LSystem::new(&lsystem_config.axiom, production_rules);
-        let generated_string = lsystem.generate(lsystem_config.n_iterations);
-        let transformations = TurtleInterpreter::interpret(&generated_string, lsystem_config.angle);
+    /// Colors are applied right away, as they do not require generating the plants again
+    fn update_species_colors(&mut self) {
+        let configs = self.species_configs.as_deref().unwrap_or_default();
+        let scene = self.scene.as_mut().unwrap();
+        for (species_index, config) in configs.iter().enumerate() {
+            scene.set_species_colors(
+                species_index,
+                (
+                    config.interpolation_color_low,
+                    config.interpolation_color_high,
+                ),
+            );
+        }
+    }
 
-        if rerandomize_positions {
-            self.scene.as_mut().unwrap().displacement_matrices =
-                self.generate_displacement_matrices();
+    /// Regenerates all species as fully grown plants
+    fn rebuild_species(&mut self) {
+        let configs = self.applied_species().to_vec();
+        let limits = self.get_growth_limits();
+        for (species_index, config) in configs.into_iter().enumerate() {
+            self.start_generation(species_index, config, limits);
         }
+    }
 
-        let final_transformations = self
-            .scene
+    fn update_wind(&mut self) {
+        let wind = self
+            .renderer
             .as_ref()
             .unwrap()
-            .displacement_matrices
+            .get_gui_controller()
+            .get_wind_parameters();
+
+        let scene = self.scene.as_mut().unwrap();
+        if scene.wind() != wind {
+            scene.set_wind(wind.clone());
+        }
+    }
+
+    fn update_growth_animation(&mut self) {
+        let now = Instant::now();
+        let delta_seconds = self
+            .last_frame_time
+            .map_or(0.0, |last| now.duration_since(last).as_secs_f32());
+        self.last_frame_time = Some(now);
+
+        let renderer = self.renderer.as_mut().unwrap();
+        renderer.advance_growth_animation(delta_seconds);
+        let mut animation = renderer.get_gui_controller().get_growth_animation().clone();
+        // Exported frames show the plant at their own time instead of the time on screen
+        if let Some(export) = &self.frame_export
+            && export.settings.source == ExportSource::Growth
+        {
+            animation.enabled = true;
+            animation.time = export.settings.frame_time(export.next_frame) * animation.speed;
+        }
+
+        if !animation.enabled {
+            if !self.species_growth.is_empty() {
+                // Dropping the jobs cancels them
+                self.species_growth.clear();
+                // Bring back the fully grown plants
+                self.rebuild_species();
+            }
+            return;
+        }
+
+        // Species animate the grammar they were generated from until the new one replaces them
+        let n_levels = self.scene.as_ref().unwrap().max_iteration_offset() as usize + 1;
+        let configs = self.scene_species_configs.clone();
+        let limits = self.get_growth_limits();
+        self.species_growth
+            .resize_with(configs.len(), SpeciesGrowth::default);
+
+        let scene = self.scene.as_mut().unwrap();
+        for (species_index, (growth, species_config)) in
+            self.species_growth.iter_mut().zip(&configs).enumerate()
+        {
+            let request = GrowthRequest {
+                lsystem_config: species_config.lsystem_config.clone(),
+                model_selection: species_config.model_selection,
+                animation: animation.clone(),
+                limits,
+                n_levels,
+            };
+            Self::update_species_growth(species_index, growth, request, scene);
+        }
+
+        let errors: Vec<String> = self
+            .species_growth
             .iter()
-            .map(|displacement_matrix| {
-                transformations
-                    .iter()
-                    .map(move |transformation| displacement_matrix * transformation)
-                    .collect::<Vec<glm::Mat4>>()
+            .zip(&configs)
+            .filter_map(|(growth, config)| {
+                growth
+                    .error
+                    .as_ref()
+                    .map(|e| format!("{}: {e}", config.name))
             })
-            .collect::<Vec<Vec<glm::Mat4>>>();
-
-        self.scene
+            .collect();
+        self.renderer
             .as_mut()
             .unwrap()
-            .update_transformations(final_transformations, target_height);
+            .set_growth_error((!errors.is_empty()).then(|| errors.join("\n")));
+    }
+
+    /// Shows the frame of the species once its worker has derived it, and starts deriving the
+    /// requested frame unless it is already shown or being derived
+    fn update_species_growth(
+        species_index: usize,
+        growth: &mut SpeciesGrowth,
+        request: GrowthRequest,
+        scene: &mut Scene,
+    ) {
+        if let Some(job) = &growth.job {
+            let Some(result) = job.try_finish() else {
+                return;
+            };
+            let job = growth.job.take().unwrap();
+            match result {
+                Ok((cache, frame)) => {
+                    growth.cache = Some(cache);
+                    // A frame of a grammar that has changed since is not shown
+                    if job.request().lsystem_config == request.lsystem_config {
+                        scene.update_transformations_with_scale(
+                            species_index,
+                            frame.levels,
+                            frame.stages,
+                            frame.scale_factor,
+                        );
+                    }
+                    growth.error = None;
+                }
+                Err(e) => growth.error = Some(e),
+            }
+            growth.shown = Some(job.request().clone());
+        }
+
+        if growth.shown.as_ref() != Some(&request) {
+            growth.job = Some(GrowthJob::spawn(request, growth.cache.take()));
+        }
+    }
+
+    /// Whether frames of the growth animation are still being derived
+    fn growth_pending(&self) -> bool {
+        self.species_growth
+            .iter()
+            .any(|growth| growth.job.is_some())
+    }
+
+    fn get_current_species(&self) -> &[SpeciesConfig] {
         self.renderer
-            .as_mut()
+            .as_ref()
             .unwrap()
-            .unset_requires_tree_regeneration();
+            .get_gui_controller()
+            .get_species()
+    }
+
+    /// Species configs the scene is brought to, which lag behind the GUI while it has errors.
+    /// They run ahead of the species in the scene while those are being generated, see
+    /// `scene_species_configs`.
+    fn applied_species(&self) -> &[SpeciesConfig] {
+        self.species_configs.as_deref().unwrap_or_default()
     }
 
-    fn get_current_lsystem_config(&self) -> &LSystemConfig {
+    fn get_growth_limits(&self) -> GrowthLimits {
         self.renderer
             .as_ref()
             .unwrap()
             .get_gui_controller()
-            .get_lsystem_config()
+            .get_growth_limits()
     }
 
     fn get_current_tree_generation_config(&self) -> &TreeGenerationConfig {
@@ -359,9 +1404,8 @@ impl App {
             .get_requires_tree_regeneration()
     }
 
-    fn requires_transformation_recalculation(&mut self) -> bool {
-        let new_lsystem_config = self.get_current_lsystem_config();
-        new_lsystem_config != self.lsystem_config.as_ref().unwrap()
+    fn requires_tree_placement(&self) -> bool {
+        self.get_current_tree_generation_config() != self.tree_generation_config.as_ref().unwrap()
             || self.requires_tree_regeneration()
     }
 }
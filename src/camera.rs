@@ -2,6 +2,7 @@ const WORLD_UP: glm::Vec3 = glm::Vec3::new(0.0, 1.0, 0.0);
 const MIN_PITCH: f32 = -89.0;
 const MAX_PITCH: f32 = 89.0;
 
+#[derive(Clone)]
 pub struct FlyCamera {
     position: glm::Vec3,
     // direction camera is facing
@@ -50,17 +51,17 @@ impl FlyCamera {
         view_matrix.into()
     }
 
+    pub fn position(&self) -> glm::Vec3 {
+        self.position
+    }
+
     fn get_position(&self) -> [f32; 3] {
         [self.position.x, self.position.y, self.position.z]
     }
 
-    fn get_projection_matrix(&self) -> [[f32; 4]; 4] {
-        let projection_matrix = glm::perspective_rh_zo(
-            self.aspect_ratio,
-            self.fovy.to_radians(),
-            self.znear,
-            self.zfar,
-        );
+    fn get_projection_matrix(&self, aspect_ratio: f32) -> [[f32; 4]; 4] {
+        let projection_matrix =
+            glm::perspective_rh_zo(aspect_ratio, self.fovy.to_radians(), self.znear, self.zfar);
         projection_matrix.into()
     }
 
@@ -109,10 +110,33 @@ impl FlyCamera {
         self.update_front();
     }
 
+    /// Moves the camera to the position, turned towards the target
+    pub fn look_at(&mut self, position: glm::Vec3, target: &glm::Vec3) {
+        self.position = position;
+        let direction = target - position;
+        if direction.norm() < f32::EPSILON {
+            return;
+        }
+        let direction = glm::normalize(&direction);
+        self.yaw = direction.z.atan2(direction.x).to_degrees();
+        self.pitch = direction.y.asin().to_degrees().clamp(MIN_PITCH, MAX_PITCH);
+        self.update_front();
+    }
+
     pub fn view_parameters(&self) -> ViewParameters {
+        self.view_parameters_with_aspect_ratio(self.aspect_ratio)
+    }
+
+    /// View of one half of a window split side by side, both halves sharing the camera
+    pub fn split_view_parameters(&self) -> ViewParameters {
+        self.view_parameters_with_aspect_ratio(self.aspect_ratio / 2.0)
+    }
+
+    /// View of an image whose proportions differ from the window's
+    pub fn view_parameters_with_aspect_ratio(&self, aspect_ratio: f32) -> ViewParameters {
         ViewParameters {
             view_matrix: self.get_view_matrix(),
-            projection_matrix: self.get_projection_matrix(),
+            projection_matrix: self.get_projection_matrix(aspect_ratio),
             camera_position: self.get_position(),
         }
     }
@@ -4,6 +4,15 @@ pub enum ModelSelection {
     Cylinder,
     Branch,
     Leaf,
+    /// Textured quad with an alpha-masked leaf
+    LeafCard,
     Twig,
     Monkey,
 }
+
+impl ModelSelection {
+    /// Thin leaves, lit from both sides and letting light through
+    pub fn is_foliage(self) -> bool {
+        matches!(self, ModelSelection::Leaf | ModelSelection::LeafCard)
+    }
+}
@@ -0,0 +1,162 @@
+use glm::{Mat4, Vec3, Vec4};
+use tobj::Model;
+
+/// Axis-aligned bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Box containing nothing, extending it with a point yields a box around that point
+    pub fn empty() -> Self {
+        Self {
+            min: Vec3::repeat(f32::INFINITY),
+            max: Vec3::repeat(f32::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_model(model: &Model) -> Self {
+        let mut aabb = Self::empty();
+        for position in model.mesh.positions.chunks(3) {
+            aabb.extend(&glm::vec3(position[0], position[1], position[2]));
+        }
+        aabb
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn extend(&mut self, point: &Vec3) {
+        self.min = glm::min2(&self.min, point);
+        self.max = glm::max2(&self.max, point);
+    }
+
+    pub fn union(&mut self, other: &Aabb) {
+        if other.is_empty() {
+            return;
+        }
+        self.extend(&other.min);
+        self.extend(&other.max);
+    }
+
+    pub fn inflated(&self, margin: f32) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        Self {
+            min: self.min - Vec3::repeat(margin),
+            max: self.max + Vec3::repeat(margin),
+        }
+    }
+
+    /// Returns the box around all corners of this box transformed by the matrix
+    pub fn transformed(&self, matrix: &Mat4) -> Self {
+        let mut aabb = Self::empty();
+        if self.is_empty() {
+            return aabb;
+        }
+        for i in 0..8 {
+            let corner = glm::vec4(
+                if i & 1 == 0 { self.min.x } else { self.max.x },
+                if i & 2 == 0 { self.min.y } else { self.max.y },
+                if i & 4 == 0 { self.min.z } else { self.max.z },
+                1.0,
+            );
+            aabb.extend(&(matrix * corner).xyz());
+        }
+        aabb
+    }
+}
+
+/// View frustum as six inward-facing planes `ax + by + cz + d >= 0`
+pub struct Frustum {
+    planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the frustum planes from a combined projection * view matrix (Gribb-Hartmann)
+    pub fn from_view_projection(view_projection: &Mat4) -> Self {
+        let row = |i: usize| view_projection.row(i).transpose();
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+
+        // The near plane uses the OpenGL clip range (-w <= z), which is the looser one
+        // for the zero-to-one projection used by the camera
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r3 + r2, r3 - r2];
+
+        Self {
+            planes: planes.map(|plane| plane / plane.xyz().norm()),
+        }
+    }
+
+    /// Returns false only if the box is entirely outside of the frustum
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+
+        self.planes.iter().all(|plane| {
+            // Corner of the box furthest along the plane normal
+            let pick = |normal: f32, min: f32, max: f32| if normal >= 0.0 { max } else { min };
+            let positive_vertex = glm::vec3(
+                pick(plane.x, aabb.min.x, aabb.max.x),
+                pick(plane.y, aabb.min.y, aabb.max.y),
+                pick(plane.z, aabb.min.z, aabb.max.z),
+            );
+            plane.xyz().dot(&positive_vertex) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_frustum() -> Frustum {
+        let view = glm::look_at(
+            &glm::vec3(0.0, 0.0, 5.0),
+            &glm::vec3(0.0, 0.0, 0.0),
+            &glm::vec3(0.0, 1.0, 0.0),
+        );
+        let projection = glm::perspective_rh_zo(1.0, 45_f32.to_radians(), 0.1, 100.0);
+        Frustum::from_view_projection(&(projection * view))
+    }
+
+    fn unit_box_at(center: Vec3) -> Aabb {
+        Aabb {
+            min: center - Vec3::repeat(0.5),
+            max: center + Vec3::repeat(0.5),
+        }
+    }
+
+    #[test]
+    fn test_box_in_front_of_camera_is_visible() {
+        assert!(test_frustum().intersects(&unit_box_at(glm::vec3(0.0, 0.0, 0.0))));
+    }
+
+    #[test]
+    fn test_box_behind_camera_is_culled() {
+        assert!(!test_frustum().intersects(&unit_box_at(glm::vec3(0.0, 0.0, 10.0))));
+    }
+
+    #[test]
+    fn test_box_beside_frustum_is_culled() {
+        assert!(!test_frustum().intersects(&unit_box_at(glm::vec3(50.0, 0.0, 0.0))));
+        assert!(!test_frustum().intersects(&unit_box_at(glm::vec3(0.0, 0.0, -200.0))));
+    }
+
+    #[test]
+    fn test_transformed_box() {
+        let aabb =
+            unit_box_at(Vec3::zeros()).transformed(&glm::translation(&glm::vec3(1.0, 2.0, 3.0)));
+        assert_eq!(aabb.min, glm::vec3(0.5, 1.5, 2.5));
+        assert_eq!(aabb.max, glm::vec3(1.5, 2.5, 3.5));
+
+        let mut union = Aabb::empty();
+        union.union(&aabb);
+        union.union(&Aabb::empty());
+        assert_eq!(union, aabb);
+    }
+}
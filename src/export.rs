@@ -0,0 +1,162 @@
+use glm::Vec3;
+use std::f32::consts::TAU;
+use std::path::{Path, PathBuf};
+
+/// What changes between the frames of an exported sequence
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExportSource {
+    /// The camera circles the scene once, looking at its center
+    Orbit,
+    /// The growth animation plays from the axiom at its configured speed
+    Growth,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportSettings {
+    pub source: ExportSource,
+    pub frames: u32,
+    /// Frames per second of the sequence, which sets how far the animations advance per frame
+    pub fps: f32,
+    pub width: u32,
+    pub height: u32,
+    /// Directory the numbered frames are saved to, frames of earlier exports are deleted
+    pub directory: String,
+}
+
+impl Default for ExportSettings {
+    fn default() -> Self {
+        Self {
+            source: ExportSource::Orbit,
+            frames: 120,
+            fps: 30.0,
+            width: 1920,
+            height: 1080,
+            directory: "frames".to_string(),
+        }
+    }
+}
+
+impl ExportSettings {
+    /// Time of the frame in seconds from the start of the sequence
+    #[allow(clippy::cast_precision_loss)]
+    pub fn frame_time(&self, frame: u32) -> f32 {
+        frame as f32 / self.fps
+    }
+
+    /// Angle of the camera around the orbit center at the frame, one full turn over the sequence
+    /// so that it loops without repeating a frame
+    #[allow(clippy::cast_precision_loss)]
+    pub fn orbit_angle(&self, frame: u32) -> f32 {
+        TAU * frame as f32 / self.frames.max(1) as f32
+    }
+
+    /// Number of frames that show the growth animation from the axiom to the fully grown plant
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    pub fn growth_frames(&self, n_iterations: u32, speed: f32) -> u32 {
+        (n_iterations as f32 / speed * self.fps).ceil() as u32 + 1
+    }
+}
+
+/// Turns the start position around the vertical axis through the center
+pub fn orbit_position(center: &Vec3, start: &Vec3, angle: f32) -> Vec3 {
+    center + glm::rotate_y_vec3(&(start - center), angle)
+}
+
+pub fn frame_path(directory: &Path, frame: u32) -> PathBuf {
+    directory.join(format!("frame_{frame:05}.png"))
+}
+
+/// Whether the file name is one given to frames by `frame_path`
+fn is_frame_name(name: &str) -> bool {
+    name.strip_prefix("frame_")
+        .and_then(|name| name.strip_suffix(".png"))
+        .is_some_and(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Deletes the frames of an earlier export from the directory, so that a shorter sequence
+/// does not end with stale frames. Other files are kept. Returns the number of deleted frames.
+pub fn clear_frames(directory: &Path) -> Result<usize, String> {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(format!("Failed to read {}: {e}", directory.display())),
+    };
+
+    let mut deleted = 0;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("Failed to read {}: {e}", directory.display()))?
+            .path();
+        if path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(is_frame_name)
+        {
+            std::fs::remove_file(&path)
+                .map_err(|e| format!("Failed to delete {}: {e}", path.display()))?;
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_timing() {
+        let settings = ExportSettings {
+            frames: 4,
+            fps: 25.0,
+            ..ExportSettings::default()
+        };
+        assert!((settings.frame_time(50) - 2.0).abs() < f32::EPSILON);
+        assert!((settings.orbit_angle(2) - TAU / 2.0).abs() < 1e-6);
+        // 3 steps at 0.5 steps per second take 6 seconds, the last frame shows the grown plant
+        assert_eq!(settings.growth_frames(3, 0.5), 151);
+        assert_eq!(
+            frame_path(Path::new("frames"), 7),
+            Path::new("frames/frame_00007.png")
+        );
+    }
+
+    #[test]
+    fn test_orbit_position() {
+        let center = glm::vec3(1.0, 0.0, 1.0);
+        let start = glm::vec3(1.0, 2.0, 5.0);
+        for frame in 0..8_u8 {
+            let angle = TAU * f32::from(frame) / 8.0;
+            let position = orbit_position(&center, &start, angle);
+            assert!((position.y - 2.0).abs() < 1e-5);
+            assert!((glm::distance(&position.xz(), &center.xz()) - 4.0).abs() < 1e-5);
+        }
+        let half_turn = orbit_position(&center, &start, TAU / 2.0);
+        assert!(glm::distance(&half_turn, &glm::vec3(1.0, 2.0, -3.0)) < 1e-5);
+    }
+
+    #[test]
+    fn test_clear_frames() {
+        let directory = std::env::temp_dir().join("lsystemtreegen_clear_frames");
+        std::fs::create_dir_all(&directory).unwrap();
+        for name in [
+            "frame_00000.png",
+            "frame_00012.png",
+            "frame_notes.png",
+            "cover.png",
+        ] {
+            std::fs::write(directory.join(name), []).unwrap();
+        }
+
+        assert_eq!(clear_frames(&directory).unwrap(), 2);
+        assert!(!frame_path(&directory, 12).exists());
+        assert!(directory.join("frame_notes.png").exists());
+        assert!(directory.join("cover.png").exists());
+        assert_eq!(clear_frames(&directory.join("missing")).unwrap(), 0);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,491 @@
+use crate::animation::{GrowthAnimation, growth_lengths};
+use crate::common::ModelSelection;
+use crate::gui::{LSystemConfig, SpeciesConfig};
+use crate::lod::MAX_LOD_LEVELS;
+use crate::lsystem::{GrowthLimits, GrowthStage};
+use crate::model_loader::load_model;
+use crate::placement::MAX_ITERATION_JITTER;
+use crate::scene::{Scene, Species};
+use crate::symbol::SymbolId;
+use crate::turtle::{Segment, TurtleInterpreter};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Predicted derivation length above which a level is rewritten on all cores instead of being
+/// streamed into the turtle if parallel rewriting is enabled, trading memory for speed
+pub const PARALLEL_DERIVATION_LENGTH: u64 = 100_000;
+
+/// Species being generated on a worker thread. Dropping the job cancels it.
+pub struct GenerationJob {
+    species_index: usize,
+    context: JobContext,
+    result: Receiver<Result<Species, String>>,
+}
+
+/// State shared between a job and its worker thread
+#[derive(Clone, Default)]
+struct JobContext {
+    cancelled: Arc<AtomicBool>,
+    /// Fraction of the work done, as bits of an `f32`
+    progress: Arc<AtomicU32>,
+}
+
+impl JobContext {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn set_progress(&self, progress: f32) {
+        self.progress.store(progress.to_bits(), Ordering::Relaxed);
+    }
+}
+
+impl GenerationJob {
+    /// Starts generating the species. With `parallel_rewriting`, large levels are derived on
+    /// all cores and held in memory as a whole instead of being streamed.
+    pub fn spawn(
+        species_index: usize,
+        config: SpeciesConfig,
+        limits: GrowthLimits,
+        parallel_rewriting: bool,
+    ) -> Self {
+        let context = JobContext::default();
+        let (sender, result) = mpsc::channel();
+
+        let worker_context = context.clone();
+        thread::spawn(move || {
+            match build_species(&config, limits, parallel_rewriting, &worker_context) {
+                Ok(None) => {}
+                Ok(Some(species)) => {
+                    // The job may have been dropped in the meantime
+                    let _ = sender.send(Ok(species));
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                }
+            }
+        });
+
+        Self {
+            species_index,
+            context,
+            result,
+        }
+    }
+
+    pub fn species_index(&self) -> usize {
+        self.species_index
+    }
+
+    /// Fraction of the work done, from 0 to 1
+    pub fn progress(&self) -> f32 {
+        f32::from_bits(self.context.progress.load(Ordering::Relaxed))
+    }
+
+    /// Returns the generated species once the worker has finished
+    pub fn try_finish(&self) -> Option<Result<Species, String>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("Generation stopped".to_string())),
+        }
+    }
+}
+
+impl Drop for GenerationJob {
+    fn drop(&mut self) {
+        self.context.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Returns `None` if the job was cancelled before finishing
+fn build_species(
+    config: &SpeciesConfig,
+    limits: GrowthLimits,
+    parallel_rewriting: bool,
+    context: &JobContext,
+) -> Result<Option<Species>, String> {
+    let Some(lod_levels) =
+        generate_lod_levels(&config.lsystem_config, limits, parallel_rewriting, context)?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(Species::new(
+        load_model(config.model_selection),
+        lod_levels,
+        config.lsystem_config.n_iterations,
+        config.lsystem_config.fractal_height,
+        (
+            config.interpolation_color_low,
+            config.interpolation_color_high,
+        ),
+    )))
+}
+
+/// Interprets the plant at the configured number of iterations, followed by levels that each
+/// use one derivation step less, enough for the youngest trees and their coarsest levels of
+/// detail. Every level counts as one step of progress.
+#[allow(clippy::cast_precision_loss)]
+fn generate_lod_levels(
+    config: &LSystemConfig,
+    limits: GrowthLimits,
+    parallel_rewriting: bool,
+    context: &JobContext,
+) -> Result<Option<Vec<Vec<Segment>>>, String> {
+    let lsystem = config.to_lsystem().with_limits(limits);
+    let min_iterations = config
+        .n_iterations
+        .saturating_sub(MAX_LOD_LEVELS - 1 + MAX_ITERATION_JITTER);
+    let n_levels = config.n_iterations - min_iterations + 1;
+
+    let mut lod_levels = Vec::new();
+    for (levels_done, n_iterations) in (1..).zip((min_iterations..=config.n_iterations).rev()) {
+        let predicted_length = lsystem
+            .estimate_growth(n_iterations)
+            .last()
+            .map_or(0, |estimate| estimate.length);
+        // Stop consuming symbols as soon as the job is cancelled
+        let segments = if parallel_rewriting && predicted_length > PARALLEL_DERIVATION_LENGTH {
+            let Some(derivation) =
+                lsystem.derive_parallel(n_iterations, || context.is_cancelled())?
+            else {
+                return Ok(None);
+            };
+            let symbols = derivation
+                .into_iter()
+                .take_while(|_| !context.is_cancelled());
+            TurtleInterpreter::interpret(symbols, lsystem.alphabet(), config.angle)
+        } else {
+            let symbols = lsystem
+                .derive(n_iterations)?
+                .take_while(|_| !context.is_cancelled());
+            TurtleInterpreter::interpret(symbols, lsystem.alphabet(), config.angle)
+        };
+        if context.is_cancelled() {
+            return Ok(None);
+        }
+        lod_levels.push(segments);
+        context.set_progress(levels_done as f32 / n_levels as f32);
+    }
+
+    Ok(Some(lod_levels))
+}
+
+/// What a frame of the growth animation of a species is derived from
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrowthRequest {
+    pub lsystem_config: LSystemConfig,
+    pub model_selection: ModelSelection,
+    pub animation: GrowthAnimation,
+    pub limits: GrowthLimits,
+    /// Number of levels, one for trees lacking 0, 1, ... derivation steps
+    pub n_levels: usize,
+}
+
+/// Frame of the growth animation of a species
+pub struct GrowthFrame {
+    pub levels: Vec<Vec<Segment>>,
+    pub stages: Vec<GrowthStage>,
+    /// Scale factor of the fully grown plant, kept constant while it grows
+    pub scale_factor: f32,
+}
+
+/// Derivation data of a species reused between frames of the growth animation
+pub struct GrowthAnimationCache {
+    lsystem_config: LSystemConfig,
+    model_selection: ModelSelection,
+    scale_factor: f32,
+    /// Derivation step shown to trees lacking 0, 1, ... derivation steps
+    steps: Vec<GrowthStep>,
+}
+
+/// Derivation step with the iteration every symbol was created in
+struct GrowthStep {
+    step: u32,
+    derivation: Vec<SymbolId>,
+    births: Vec<u32>,
+}
+
+/// Frame of the growth animation being derived on a worker thread, which takes the cache
+/// along and hands it back with the frame. Dropping the job cancels it.
+pub struct GrowthJob {
+    request: GrowthRequest,
+    context: JobContext,
+    result: Receiver<Result<(GrowthAnimationCache, GrowthFrame), String>>,
+}
+
+impl GrowthJob {
+    pub fn spawn(request: GrowthRequest, cache: Option<GrowthAnimationCache>) -> Self {
+        let context = JobContext::default();
+        let (sender, result) = mpsc::channel();
+
+        let worker_context = context.clone();
+        let worker_request = request.clone();
+        thread::spawn(
+            move || match derive_growth_frame(&worker_request, cache, &worker_context) {
+                Ok(None) => {}
+                Ok(Some(frame)) => {
+                    // The job may have been dropped in the meantime
+                    let _ = sender.send(Ok(frame));
+                }
+                Err(e) => {
+                    let _ = sender.send(Err(e));
+                }
+            },
+        );
+
+        Self {
+            request,
+            context,
+            result,
+        }
+    }
+
+    pub fn request(&self) -> &GrowthRequest {
+        &self.request
+    }
+
+    /// Returns the frame and the cache once the worker has finished
+    pub fn try_finish(&self) -> Option<Result<(GrowthAnimationCache, GrowthFrame), String>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err("Growth animation stopped".to_string())),
+        }
+    }
+}
+
+impl Drop for GrowthJob {
+    fn drop(&mut self) {
+        self.context.cancelled.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Interprets every level of the frame, reusing the cache if it belongs to the same grammar
+/// and model. Returns `None` if the job was cancelled before finishing.
+fn derive_growth_frame(
+    request: &GrowthRequest,
+    cache: Option<GrowthAnimationCache>,
+    context: &JobContext,
+) -> Result<Option<(GrowthAnimationCache, GrowthFrame)>, String> {
+    let config = &request.lsystem_config;
+    let lsystem = config.to_lsystem().with_limits(request.limits);
+
+    let mut cache = match cache {
+        Some(cache)
+            if cache.lsystem_config == *config
+                && cache.model_selection == request.model_selection =>
+        {
+            cache
+        }
+        _ => {
+            let full_grown = TurtleInterpreter::interpret(
+                lsystem
+                    .derive(config.n_iterations)?
+                    .take_while(|_| !context.is_cancelled()),
+                lsystem.alphabet(),
+                config.angle,
+            );
+            let scale_factor = Scene::height_scale_factor(
+                &load_model(request.model_selection),
+                &full_grown,
+                config.fractal_height,
+            );
+            GrowthAnimationCache {
+                lsystem_config: config.clone(),
+                model_selection: request.model_selection,
+                scale_factor,
+                steps: Vec::new(),
+            }
+        }
+    };
+
+    cache.steps.truncate(request.n_levels);
+    let mut levels = Vec::with_capacity(request.n_levels);
+    let mut stages = Vec::with_capacity(request.n_levels);
+    for iteration_offset in 0..request.n_levels {
+        if context.is_cancelled() {
+            return Ok(None);
+        }
+        // Trees lacking derivation steps show the plant as it was that many steps earlier
+        #[allow(clippy::cast_precision_loss)]
+        let animation = GrowthAnimation {
+            time: request.animation.time - iteration_offset as f32,
+            ..request.animation.clone()
+        };
+        let level = if animation.timed {
+            let t = animation.timed_derivation_time(lsystem.min_terminal_age());
+            let modules = lsystem.derive_at(t)?;
+            stages.push(GrowthStage::Time(t));
+            TurtleInterpreter::interpret_scaled(
+                modules
+                    .iter()
+                    .map(|module| (module.symbol, module.length(animation.growth_function))),
+                lsystem.alphabet(),
+                config.angle,
+            )
+        } else {
+            let (step, progress) = animation.step_and_progress(config.n_iterations);
+            if cache
+                .steps
+                .get(iteration_offset)
+                .is_none_or(|cached| cached.step != step)
+            {
+                let (derivation, births) = lsystem.generate_with_births(step)?;
+                let growth_step = GrowthStep {
+                    step,
+                    derivation,
+                    births,
+                };
+                if iteration_offset < cache.steps.len() {
+                    cache.steps[iteration_offset] = growth_step;
+                } else {
+                    cache.steps.push(growth_step);
+                }
+            }
+
+            let growth_step = &cache.steps[iteration_offset];
+            stages.push(GrowthStage::Iterations(step));
+            let lengths = growth_lengths(&growth_step.births, step, progress);
+            TurtleInterpreter::interpret_scaled(
+                growth_step.derivation.iter().copied().zip(lengths),
+                lsystem.alphabet(),
+                config.angle,
+            )
+        };
+        levels.push(level);
+    }
+
+    let frame = GrowthFrame {
+        levels,
+        stages,
+        scale_factor: cache.scale_factor,
+    };
+    Ok(Some((cache, frame)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn wait_for(job: &GenerationJob) -> Result<Species, String> {
+        for _ in 0..1000 {
+            if let Some(result) = job.try_finish() {
+                return result;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Generation did not finish");
+    }
+
+    #[test]
+    fn test_lod_levels_match_derivation() {
+        let config = LSystemConfig {
+            n_iterations: 5,
+            ..LSystemConfig::default()
+        };
+        let lsystem = config.to_lsystem();
+        for parallel_rewriting in [false, true] {
+            let lod_levels = generate_lod_levels(
+                &config,
+                GrowthLimits::default(),
+                parallel_rewriting,
+                &JobContext::default(),
+            )
+            .unwrap()
+            .unwrap();
+
+            assert_eq!(
+                lod_levels.len(),
+                (MAX_LOD_LEVELS + MAX_ITERATION_JITTER) as usize
+            );
+            for (level, segments) in (0..).zip(&lod_levels) {
+                let derivation = lsystem.derive(config.n_iterations - level).unwrap();
+                assert_eq!(
+                    segments.len(),
+                    TurtleInterpreter::interpret(derivation, lsystem.alphabet(), config.angle)
+                        .len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_cancelled_generation() {
+        let context = JobContext::default();
+        context.cancelled.store(true, Ordering::Relaxed);
+        for parallel_rewriting in [false, true] {
+            let result = generate_lod_levels(
+                &LSystemConfig::default(),
+                GrowthLimits::default(),
+                parallel_rewriting,
+                &context,
+            );
+            assert_eq!(result.map(|levels| levels.is_none()), Ok(true));
+        }
+    }
+
+    #[test]
+    fn test_generation_job() {
+        let job = GenerationJob::spawn(3, SpeciesConfig::default(), GrowthLimits::default(), false);
+        let species = wait_for(&job).unwrap();
+        assert_eq!(job.species_index(), 3);
+        assert!((job.progress() - 1.0).abs() < f32::EPSILON);
+        assert!(!species.segments(0).is_empty());
+
+        let mut config = SpeciesConfig::default();
+        config.lsystem_config.n_iterations = 6;
+        let job = GenerationJob::spawn(0, config, GrowthLimits::default(), false);
+        assert!(wait_for(&job).is_err());
+    }
+
+    #[test]
+    fn test_growth_job() {
+        let lsystem_config = LSystemConfig {
+            n_iterations: 3,
+            ..LSystemConfig::default()
+        };
+        let request = GrowthRequest {
+            lsystem_config: lsystem_config.clone(),
+            model_selection: ModelSelection::Branch,
+            animation: GrowthAnimation {
+                enabled: true,
+                time: 2.5,
+                ..GrowthAnimation::default()
+            },
+            limits: GrowthLimits::default(),
+            n_levels: 2,
+        };
+        let (cache, frame) = derive_growth_frame(&request, None, &JobContext::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            frame.stages,
+            [GrowthStage::Iterations(3), GrowthStage::Iterations(2)]
+        );
+        assert_eq!(frame.levels.len(), 2);
+        assert_eq!(cache.steps.len(), 2);
+
+        // The cache of another grammar is not reused
+        let other = GrowthRequest {
+            lsystem_config: LSystemConfig {
+                n_iterations: 2,
+                ..lsystem_config
+            },
+            ..request.clone()
+        };
+        let job = GrowthJob::spawn(other.clone(), Some(cache));
+        let (cache, _) = (0..1000)
+            .find_map(|_| {
+                thread::sleep(Duration::from_millis(10));
+                job.try_finish()
+            })
+            .expect("Growth animation did not finish")
+            .unwrap();
+        assert_eq!(job.request(), &other);
+        assert_eq!(cache.lsystem_config, other.lsystem_config);
+    }
+}
@@ -1,4 +1,20 @@
+use crate::animation::GrowthAnimation;
 use crate::common::ModelSelection;
+use crate::export::{ExportSettings, ExportSource};
+use crate::generation::PARALLEL_DERIVATION_LENGTH;
+use crate::lod::LodSettings;
+use crate::lsystem::{DEFAULT_TERMINAL_AGE, GrowthFunction, GrowthLimits, HistoryStep, LSystem};
+use crate::picking::Inspection;
+use crate::placement::{InstanceVariation, MAX_ITERATION_JITTER, PlacementStrategy};
+use crate::renderer::{FoliageSettings, RenderStats};
+use crate::scene::WindParameters;
+use crate::screenshot::ScreenshotSettings;
+use crate::stats::PlantStats;
+use crate::symbol::{self, Alphabet};
+use crate::terrain::{TerrainConfig, TerrainSource};
+use crate::turtle::TurtleInterpreter;
+use crate::undo::{ConfigSnapshot, UndoHistory};
+use crate::validation::{self, Diagnostic, Location, Severity};
 use egui::Ui;
 use egui_glium::EguiGlium;
 use egui_glium::egui_winit::egui::ViewportId;
@@ -8,25 +24,183 @@ use winit::event::WindowEvent;
 use winit::event_loop::ActiveEventLoop;
 use winit::window::Window;
 
+/// Number of leading symbols of every derivation step shown in the derivation history at first
+const HISTORY_LENGTH: usize = 500;
+/// Most leading symbols of every derivation step "Show more" expands the history to
+const MAX_HISTORY_LENGTH: usize = 16_000;
+/// Colors of the symbols produced by each rule in the derivation history
+const RULE_COLORS: [egui::Color32; 6] = [
+    egui::Color32::LIGHT_BLUE,
+    egui::Color32::LIGHT_GREEN,
+    egui::Color32::GOLD,
+    egui::Color32::LIGHT_RED,
+    egui::Color32::KHAKI,
+    egui::Color32::from_rgb(200, 150, 255),
+];
+
+const UNDO_SHORTCUT: egui::KeyboardShortcut =
+    egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z);
+const REDO_SHORTCUT: egui::KeyboardShortcut = egui::KeyboardShortcut::new(
+    egui::Modifiers::COMMAND.plus(egui::Modifiers::SHIFT),
+    egui::Key::Z,
+);
+
+/// Derivation history laid out for the derivation history window
+struct HistoryView {
+    lsystem_config: LSystemConfig,
+    history_length: usize,
+    steps: Vec<HistoryStepView>,
+}
+
+struct HistoryStepView {
+    length: u64,
+    counts: String,
+    symbols: egui::text::LayoutJob,
+    /// Number of symbols of the step that are not shown
+    hidden: u64,
+}
+
+impl HistoryView {
+    fn new(lsystem_config: &LSystemConfig, history_length: usize) -> Self {
+        let lsystem = lsystem_config.to_lsystem();
+        let steps = lsystem
+            .history(lsystem_config.n_iterations, history_length)
+            .iter()
+            .map(|step| HistoryStepView {
+                length: step.length,
+                counts: GuiController::symbol_counts(step, lsystem.alphabet()),
+                symbols: GuiController::highlighted_step(step, lsystem.alphabet()),
+                hidden: step.length.saturating_sub(step.symbols.len() as u64),
+            })
+            .collect();
+        Self {
+            lsystem_config: lsystem_config.clone(),
+            history_length,
+            steps,
+        }
+    }
+}
+
+#[allow(clippy::struct_excessive_bools)]
 pub struct GuiController {
     egui_glium: EguiGlium,
-    model_selection: ModelSelection,
-    lsystem_config: LSystemConfig,
-    preset_selection: PresetSelection,
+    species: Vec<SpeciesConfig>,
+    /// Index of the species edited in the control panel
+    selected_species: usize,
     shading_mode: ShadingMode,
-    interpolation_color_low: [f32; 3],
-    interpolation_color_high: [f32; 3],
     tree_generation_config: TreeGenerationConfig,
     requires_tree_regeneration: bool,
+    growth_animation: GrowthAnimation,
+    growth_limits: GrowthLimits,
+    /// Whether large plants are derived on all cores instead of being streamed
+    parallel_rewriting: bool,
+    /// Why the growth animation cannot be shown, if it cannot
+    growth_error: Option<String>,
+    /// Progress of species being generated in the background, if there are any
+    generation_progress: Option<f32>,
+    /// Statistics of every species in the scene, in the same order
+    plant_stats: Vec<PlantStats>,
+    show_history: bool,
+    /// Number of leading symbols of every derivation step shown in the derivation history
+    history_length: usize,
+    /// Derivation history as last shown, rebuilt when the grammar or `history_length` changes
+    history_view: Option<HistoryView>,
+    wind_parameters: WindParameters,
+    frustum_culling: bool,
+    show_stats: bool,
+    /// Repetitions of model textures per unit of segment length
+    texture_tiling: f32,
+    foliage_settings: FoliageSettings,
+    lod_settings: LodSettings,
+    terrain_config: TerrainConfig,
+    terrain_error: Option<String>,
+    placement_error: Option<String>,
+    inspection: Option<Inspection>,
+    edit_tool: EditTool,
+    scene_path: String,
+    scene_request: Option<SceneRequest>,
+    /// Outcome of the last scene request, a message or an error
+    scene_status: Option<Result<String, String>>,
+    /// Whether trees were placed by hand since they were last generated
+    layout_edited: bool,
+    undo_history: UndoHistory<ConfigSnapshot>,
+    undo_request: Option<UndoRequest>,
+    comparison: ComparisonConfig,
+    screenshot_settings: ScreenshotSettings,
+    screenshot_request: Option<ScreenshotRequest>,
+    /// Outcome of the last screenshot, the saved path or an error
+    screenshot_status: Option<Result<String, String>>,
+    export_settings: ExportSettings,
+    export_request: Option<ExportRequest>,
+    /// Frames exported so far and the number of frames, while a sequence is being exported
+    export_progress: Option<(u32, u32)>,
+    /// Outcome of the last export, a message or an error
+    export_status: Option<Result<String, String>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum UndoRequest {
+    Undo,
+    Redo,
+    /// Restore the history entry at the given index
+    GoTo(usize),
+}
+
+/// Action performed when clicking the scene in GUI mode
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum EditTool {
+    #[default]
+    Inspect,
+    Plant,
+    Move,
+    Rotate,
+    Delete,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SceneRequest {
+    Save,
+    Load,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ScreenshotRequest {
+    /// The scene at the size of the window
+    Window,
+    /// The scene at the size given in the screenshot settings
+    HighResolution,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExportRequest {
+    Start,
+    Cancel,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct LSystemConfig {
     pub axiom: String,
-    pub production_rules: Vec<(char, String)>,
+    /// Pairs of predecessor symbol name and successor
+    pub production_rules: Vec<(String, String)>,
     pub n_iterations: u32,
     pub angle: f32,
     pub fractal_height: f32,
+    /// Terminal ages of symbols for timed growth, symbols without an entry use the default
+    pub terminal_ages: Vec<(String, f32)>,
+}
+
+impl LSystemConfig {
+    pub fn to_lsystem(&self) -> LSystem {
+        let production_rules = self
+            .production_rules
+            .iter()
+            .map(|(predecessor, successor)| (predecessor.as_str(), successor.as_str()));
+        let terminal_ages = self
+            .terminal_ages
+            .iter()
+            .map(|(symbol, terminal_age)| (symbol.as_str(), *terminal_age));
+        LSystem::new(&self.axiom, production_rules).with_terminal_ages(terminal_ages)
+    }
 }
 
 impl Default for LSystemConfig {
@@ -35,6 +209,58 @@ impl Default for LSystemConfig {
     }
 }
 
+/// Second grammar drawn in the right half of the screen for comparison with the selected species
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComparisonConfig {
+    pub enabled: bool,
+    pub lsystem_config: LSystemConfig,
+    pub preset_selection: PresetSelection,
+}
+
+impl Default for ComparisonConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lsystem_config: LSystemConfig::default(),
+            preset_selection: PresetSelection::Tree3D,
+        }
+    }
+}
+
+/// Kind of plant in the scene with its own grammar, look and frequency
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeciesConfig {
+    pub name: String,
+    pub lsystem_config: LSystemConfig,
+    pub preset_selection: PresetSelection,
+    pub model_selection: ModelSelection,
+    pub interpolation_color_low: [f32; 3],
+    pub interpolation_color_high: [f32; 3],
+    /// Relative frequency of the species among placed trees
+    pub weight: f32,
+}
+
+impl Default for SpeciesConfig {
+    fn default() -> Self {
+        Self {
+            name: "Tree".to_string(),
+            lsystem_config: LSystemConfig::default(),
+            preset_selection: PresetSelection::Tree3D,
+            model_selection: ModelSelection::Cylinder,
+            interpolation_color_low: [0.28, 0.14, 0.01],
+            interpolation_color_high: [0.08, 0.2, 0.01],
+            weight: 1.0,
+        }
+    }
+}
+
+impl SpeciesConfig {
+    /// Returns true if trees of the other species have to be generated anew to match this one
+    pub fn requires_regeneration(&self, other: &SpeciesConfig) -> bool {
+        self.lsystem_config != other.lsystem_config || self.model_selection != other.model_selection
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct TreeGenerationConfig {
     num_trees: u8,
@@ -42,6 +268,12 @@ pub struct TreeGenerationConfig {
     xmax: i32,
     zmin: i32,
     zmax: i32,
+    placement_strategy: PlacementStrategy,
+    seed: u64,
+    /// Minimum distance between trees for Poisson-disk placement
+    min_spacing: f32,
+    density_map_path: String,
+    variation: InstanceVariation,
 }
 
 impl TreeGenerationConfig {
@@ -56,6 +288,26 @@ impl TreeGenerationConfig {
     pub fn get_z_bounds(&self) -> (i32, i32) {
         (self.zmin, self.zmax)
     }
+
+    pub fn get_placement_strategy(&self) -> PlacementStrategy {
+        self.placement_strategy
+    }
+
+    pub fn get_seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn get_min_spacing(&self) -> f32 {
+        self.min_spacing
+    }
+
+    pub fn get_density_map_path(&self) -> &str {
+        &self.density_map_path
+    }
+
+    pub fn get_variation(&self) -> &InstanceVariation {
+        &self.variation
+    }
 }
 
 impl Default for TreeGenerationConfig {
@@ -66,6 +318,11 @@ impl Default for TreeGenerationConfig {
             xmax: 10,
             zmin: -10,
             zmax: 10,
+            placement_strategy: PlacementStrategy::default(),
+            seed: 0,
+            min_spacing: 2.0,
+            density_map_path: "assets/density/clearing.png".to_string(),
+            variation: InstanceVariation::default(),
         }
     }
 }
@@ -84,41 +341,52 @@ impl PresetSelection {
         match self {
             PresetSelection::Tree3D => LSystemConfig {
                 axiom: "F".to_string(),
-                production_rules: vec![('F', "F[+F][&F][\\F]F[-F][^F][/F]F".to_string())],
+                production_rules: vec![(
+                    "F".to_string(),
+                    "F[+F][&F][\\F]F[-F][^F][/F]F".to_string(),
+                )],
                 n_iterations: 3,
                 angle: 25.0,
                 fractal_height: 3.0,
+                terminal_ages: Vec::new(),
             },
             PresetSelection::Bush => LSystemConfig {
                 axiom: "F".to_string(),
-                production_rules: vec![('F', "FF[++F][-F][&F][^F]".to_string())],
+                production_rules: vec![("F".to_string(), "FF[++F][-F][&F][^F]".to_string())],
                 n_iterations: 4,
                 angle: 22.5,
                 fractal_height: 3.0,
+                terminal_ages: Vec::new(),
             },
             PresetSelection::Seaweed => LSystemConfig {
                 axiom: "F".to_string(),
-                production_rules: vec![('F', "F[+F]F[-F][F]".to_string())],
+                production_rules: vec![("F".to_string(), "F[+F]F[-F][F]".to_string())],
                 n_iterations: 4,
                 angle: 20.0,
                 fractal_height: 3.0,
+                terminal_ages: Vec::new(),
             },
             PresetSelection::TrunkTree => LSystemConfig {
                 axiom: "FX".to_string(),
                 production_rules: vec![
-                    ('X', "[+FX][-FX][&FX][^FX][\\FX][/FX]".to_string()),
-                    ('F', "FF".to_string()),
+                    (
+                        "X".to_string(),
+                        "[+FX][-FX][&FX][^FX][\\FX][/FX]".to_string(),
+                    ),
+                    ("F".to_string(), "FF".to_string()),
                 ],
                 n_iterations: 3,
                 angle: 28.0,
                 fractal_height: 4.0,
+                terminal_ages: Vec::new(),
             },
             PresetSelection::Custom => LSystemConfig {
                 axiom: "F".to_string(),
-                production_rules: vec![('F', "F".to_string())],
+                production_rules: vec![("F".to_string(), "F".to_string())],
                 n_iterations: 1,
                 angle: 25.0,
                 fractal_height: 3.0,
+                terminal_ages: Vec::new(),
             },
         }
     }
@@ -147,16 +415,53 @@ impl GuiController {
         window: &Window,
         event_loop: &ActiveEventLoop,
     ) -> Self {
+        let species = vec![SpeciesConfig::default()];
+        let tree_generation_config = TreeGenerationConfig::default();
+        let undo_history = UndoHistory::new(ConfigSnapshot {
+            species: species.clone(),
+            tree_generation_config: tree_generation_config.clone(),
+        });
         Self {
             egui_glium: EguiGlium::new(ViewportId::ROOT, display, window, event_loop),
-            model_selection: ModelSelection::Cylinder,
+            species,
+            selected_species: 0,
             shading_mode: ShadingMode::Phong,
-            lsystem_config: LSystemConfig::default(),
-            preset_selection: PresetSelection::Tree3D,
-            interpolation_color_low: [0.28, 0.14, 0.01],
-            interpolation_color_high: [0.08, 0.2, 0.01],
-            tree_generation_config: TreeGenerationConfig::default(),
+            tree_generation_config,
             requires_tree_regeneration: false,
+            growth_animation: GrowthAnimation::default(),
+            growth_limits: GrowthLimits::default(),
+            parallel_rewriting: false,
+            growth_error: None,
+            generation_progress: None,
+            plant_stats: Vec::new(),
+            show_history: false,
+            history_length: HISTORY_LENGTH,
+            history_view: None,
+            wind_parameters: WindParameters::default(),
+            frustum_culling: true,
+            show_stats: false,
+            texture_tiling: 1.0,
+            foliage_settings: FoliageSettings::default(),
+            lod_settings: LodSettings::default(),
+            terrain_config: TerrainConfig::default(),
+            terrain_error: None,
+            placement_error: None,
+            inspection: None,
+            edit_tool: EditTool::default(),
+            scene_path: "scenes/forest.txt".to_string(),
+            scene_request: None,
+            scene_status: None,
+            layout_edited: false,
+            undo_history,
+            undo_request: None,
+            comparison: ComparisonConfig::default(),
+            screenshot_settings: ScreenshotSettings::default(),
+            screenshot_request: None,
+            screenshot_status: None,
+            export_settings: ExportSettings::default(),
+            export_request: None,
+            export_progress: None,
+            export_status: None,
         }
     }
 
@@ -168,30 +473,259 @@ impl GuiController {
         self.requires_tree_regeneration = false;
     }
 
-    pub fn get_lsystem_config(&self) -> &LSystemConfig {
-        &self.lsystem_config
+    pub fn get_species(&self) -> &[SpeciesConfig] {
+        &self.species
+    }
+
+    /// Replaces all species, as when a scene is loaded
+    pub fn set_species(&mut self, species: Vec<SpeciesConfig>) {
+        self.species = species;
+        self.selected_species = self.selected_species.min(self.species.len() - 1);
     }
 
     pub fn get_tree_generation_config(&self) -> &TreeGenerationConfig {
         &self.tree_generation_config
     }
 
-    pub fn get_model_selection(&self) -> &ModelSelection {
-        &self.model_selection
+    pub fn get_growth_animation(&self) -> &GrowthAnimation {
+        &self.growth_animation
     }
 
-    pub fn get_shading_mode(&self) -> &ShadingMode {
-        &self.shading_mode
+    pub fn advance_growth_animation(&mut self, delta_seconds: f32) {
+        self.growth_animation
+            .advance(delta_seconds, self.max_iterations());
+    }
+
+    /// Number of iterations of the species that takes the longest to grow
+    fn max_iterations(&self) -> u32 {
+        self.species
+            .iter()
+            .map(|species| species.lsystem_config.n_iterations)
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn get_growth_limits(&self) -> GrowthLimits {
+        self.growth_limits
+    }
+
+    pub fn get_parallel_rewriting(&self) -> bool {
+        self.parallel_rewriting
+    }
+
+    pub fn set_growth_error(&mut self, error: Option<String>) {
+        self.growth_error = error;
+    }
+
+    pub fn set_generation_progress(&mut self, progress: Option<f32>) {
+        self.generation_progress = progress;
+    }
+
+    pub fn set_plant_stats(&mut self, plant_stats: Vec<PlantStats>) {
+        self.plant_stats = plant_stats;
+    }
+
+    pub fn get_wind_parameters(&self) -> &WindParameters {
+        &self.wind_parameters
+    }
+
+    pub fn get_frustum_culling(&self) -> bool {
+        self.frustum_culling
+    }
+
+    pub fn get_texture_tiling(&self) -> f32 {
+        self.texture_tiling
+    }
+
+    pub fn get_foliage_settings(&self) -> &FoliageSettings {
+        &self.foliage_settings
+    }
+
+    pub fn get_show_stats(&self) -> bool {
+        self.show_stats
+    }
+
+    pub fn get_lod_settings(&self) -> &LodSettings {
+        &self.lod_settings
+    }
+
+    pub fn get_terrain_config(&self) -> &TerrainConfig {
+        &self.terrain_config
+    }
+
+    pub fn set_terrain_error(&mut self, error: Option<String>) {
+        self.terrain_error = error;
+    }
+
+    pub fn set_placement_error(&mut self, error: Option<String>) {
+        self.placement_error = error;
     }
 
-    pub fn get_interpolation_colors(&self) -> ([f32; 3], [f32; 3]) {
-        (self.interpolation_color_low, self.interpolation_color_high)
+    pub fn get_shading_mode(&self) -> &ShadingMode {
+        &self.shading_mode
     }
 
     pub fn handle_event(&mut self, event: &WindowEvent, window: &Window) {
         let _ = self.egui_glium.on_event(window, event);
     }
 
+    /// Returns true if the pointer is over a GUI element, so clicks are not meant for the scene
+    pub fn wants_pointer_input(&self) -> bool {
+        let ctx = self.egui_glium.egui_ctx();
+        ctx.wants_pointer_input() || ctx.is_pointer_over_area()
+    }
+
+    pub fn set_inspection(&mut self, inspection: Option<Inspection>) {
+        self.inspection = inspection;
+    }
+
+    pub fn get_edit_tool(&self) -> EditTool {
+        self.edit_tool
+    }
+
+    /// Index of the species edited in the control panel, which is also the one planted
+    pub fn get_selected_species(&self) -> usize {
+        self.selected_species
+    }
+
+    pub fn get_scene_path(&self) -> &str {
+        &self.scene_path
+    }
+
+    pub fn take_scene_request(&mut self) -> Option<SceneRequest> {
+        self.scene_request.take()
+    }
+
+    pub fn set_scene_status(&mut self, status: Result<String, String>) {
+        self.scene_status = Some(status);
+    }
+
+    pub fn set_layout_edited(&mut self, edited: bool) {
+        self.layout_edited = edited;
+    }
+
+    /// Index of the species shown as variant A and the grammar of variant B, if split view is on
+    pub fn get_comparison(&self) -> Option<(usize, &LSystemConfig)> {
+        self.comparison
+            .enabled
+            .then_some((self.selected_species, &self.comparison.lsystem_config))
+    }
+
+    /// Whether variant B is drawn next to variant A. It is hidden while the growth animation
+    /// plays or is exported, as variant B is only generated fully grown.
+    pub fn shows_split_view(&self) -> bool {
+        let growth_export =
+            self.export_progress.is_some() && self.export_settings.source == ExportSource::Growth;
+        self.comparison.enabled && !self.growth_animation.enabled && !growth_export
+    }
+
+    pub fn get_screenshot_settings(&self) -> &ScreenshotSettings {
+        &self.screenshot_settings
+    }
+
+    pub fn take_screenshot_request(&mut self) -> Option<ScreenshotRequest> {
+        self.screenshot_request.take()
+    }
+
+    pub fn set_screenshot_status(&mut self, status: Result<String, String>) {
+        self.screenshot_status = Some(status);
+    }
+
+    pub fn get_export_settings(&self) -> &ExportSettings {
+        &self.export_settings
+    }
+
+    pub fn take_export_request(&mut self) -> Option<ExportRequest> {
+        self.export_request.take()
+    }
+
+    pub fn set_export_progress(&mut self, progress: Option<(u32, u32)>) {
+        self.export_progress = progress;
+    }
+
+    pub fn set_export_status(&mut self, status: Result<String, String>) {
+        self.export_status = Some(status);
+    }
+
+    fn snapshot(&self) -> ConfigSnapshot {
+        ConfigSnapshot {
+            species: self.species.clone(),
+            tree_generation_config: self.tree_generation_config.clone(),
+        }
+    }
+
+    /// Records finished edits, then performs undo or redo requested with the keyboard or GUI
+    fn update_undo_history(&mut self) {
+        let ctx = self.egui_glium.egui_ctx().clone();
+        // While text is typed, Ctrl+Z is handled by the text field itself
+        let typing = ctx.wants_keyboard_input();
+        if !typing {
+            ctx.input_mut(|input| {
+                if input.consume_shortcut(&REDO_SHORTCUT) {
+                    self.undo_request = Some(UndoRequest::Redo);
+                } else if input.consume_shortcut(&UNDO_SHORTCUT) {
+                    self.undo_request = Some(UndoRequest::Undo);
+                }
+            });
+        }
+
+        // An edit is recorded once the slider is released or the text field left,
+        // so that it is undone as a whole
+        let snapshot = self.snapshot();
+        let editing = typing || ctx.input(|input| input.pointer.any_down());
+        if snapshot != *self.undo_history.current() && (!editing || self.undo_request.is_some()) {
+            let label = snapshot.describe_edit(self.undo_history.current());
+            self.undo_history.record(label, snapshot);
+        }
+
+        let restored = match self.undo_request.take() {
+            Some(UndoRequest::Undo) => self.undo_history.undo(),
+            Some(UndoRequest::Redo) => self.undo_history.redo(),
+            Some(UndoRequest::GoTo(index)) => self.undo_history.go_to(index),
+            None => None,
+        };
+        if let Some(snapshot) = restored.cloned() {
+            self.species = snapshot.species;
+            self.tree_generation_config = snapshot.tree_generation_config;
+            self.selected_species = self.selected_species.min(self.species.len() - 1);
+        }
+    }
+
+    fn ui_undo_panel(
+        undo_history: &UndoHistory<ConfigSnapshot>,
+        undo_request: &mut Option<UndoRequest>,
+        ui: &mut Ui,
+    ) {
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(undo_history.can_undo(), egui::Button::new("Undo"))
+                .on_hover_text("Ctrl+Z")
+                .clicked()
+            {
+                *undo_request = Some(UndoRequest::Undo);
+            }
+            if ui
+                .add_enabled(undo_history.can_redo(), egui::Button::new("Redo"))
+                .on_hover_text("Ctrl+Shift+Z")
+                .clicked()
+            {
+                *undo_request = Some(UndoRequest::Redo);
+            }
+        });
+        ui.collapsing("Edit history", |ui| {
+            egui::ScrollArea::vertical()
+                .max_height(150.0)
+                .show(ui, |ui| {
+                    for (index, label) in undo_history.labels().enumerate() {
+                        let current = index == undo_history.current_index();
+                        if ui.selectable_label(current, label).clicked() {
+                            *undo_request = Some(UndoRequest::GoTo(index));
+                        }
+                    }
+                });
+        });
+    }
+
     fn ui_control_panel(
         model_selection: &mut ModelSelection,
         shading_mode: &mut ShadingMode,
@@ -204,6 +738,7 @@ impl GuiController {
                 ui.selectable_value(model_selection, ModelSelection::Branch, "Branch");
                 ui.selectable_value(model_selection, ModelSelection::Twig, "Twig");
                 ui.selectable_value(model_selection, ModelSelection::Leaf, "Leaf");
+                ui.selectable_value(model_selection, ModelSelection::LeafCard, "Leaf card");
                 ui.selectable_value(model_selection, ModelSelection::Monkey, "Monkey");
             });
 
@@ -213,6 +748,231 @@ impl GuiController {
         ui.radio_value(shading_mode, ShadingMode::Phong, "Phong");
     }
 
+    fn ui_render_options(
+        frustum_culling: &mut bool,
+        show_stats: &mut bool,
+        lod_settings: &mut LodSettings,
+        texture_tiling: &mut f32,
+        foliage_settings: &mut FoliageSettings,
+        ui: &mut Ui,
+    ) {
+        ui.add(
+            egui::Slider::new(texture_tiling, 0.1..=10.0)
+                .logarithmic(true)
+                .text("Texture repeats per unit length"),
+        );
+        ui.add(
+            egui::Slider::new(&mut foliage_settings.alpha_cutoff, 0.0..=1.0)
+                .text("Leaf alpha cutoff"),
+        );
+        ui.add(
+            egui::Slider::new(&mut foliage_settings.translucency, 0.0..=1.0)
+                .text("Leaf translucency"),
+        );
+        ui.checkbox(frustum_culling, "Frustum culling");
+        ui.checkbox(show_stats, "Show stats overlay");
+        ui.checkbox(&mut lod_settings.enabled, "Level of detail");
+        if lod_settings.enabled {
+            ui.add(
+                egui::Slider::new(&mut lod_settings.bias, 0.25..=4.0)
+                    .logarithmic(true)
+                    .text("LOD bias"),
+            );
+        }
+    }
+
+    fn ui_stats_overlay(stats: &RenderStats, ctx: &egui::Context) {
+        egui::Area::new(egui::Id::new("stats_overlay"))
+            .anchor(egui::Align2::RIGHT_TOP, egui::vec2(-10.0, 10.0))
+            .interactable(false)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).show(ui, |ui| {
+                    ui.label(format!("Trees drawn: {}", stats.drawn_trees));
+                    ui.label(format!("Trees culled: {}", stats.culled_trees));
+                    ui.label(format!("Segments drawn: {}", stats.drawn_segments));
+                    ui.label(format!("Triangles drawn: {}", stats.drawn_triangles));
+                });
+            });
+    }
+
+    fn ui_species_list(species: &mut Vec<SpeciesConfig>, selected: &mut usize, ui: &mut Ui) {
+        ui.label("Species:");
+        let mut to_remove = None;
+        for (i, species_config) in species.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                ui.selectable_value(selected, i, &species_config.name);
+                ui.add(
+                    egui::DragValue::new(&mut species_config.weight)
+                        .range(0.0..=100.0)
+                        .speed(0.05)
+                        .prefix("weight: "),
+                );
+                if ui.button("❌").clicked() {
+                    to_remove = Some(i);
+                }
+            });
+        }
+
+        // At least one species is kept so there is always something to edit
+        if let Some(i) = to_remove
+            && species.len() > 1
+        {
+            species.remove(i);
+        }
+        if ui.button("➕ Add Species").clicked() {
+            let mut new_species = species[*selected].clone();
+            new_species.name = format!("Species {}", species.len() + 1);
+            species.push(new_species);
+            *selected = species.len() - 1;
+        }
+        *selected = (*selected).min(species.len() - 1);
+
+        ui.horizontal(|ui| {
+            ui.label("Name:");
+            ui.text_edit_singleline(&mut species[*selected].name);
+        });
+    }
+
+    fn ui_inspector(inspection: &Inspection, ctx: &egui::Context) {
+        egui::Window::new("Inspector")
+            .default_pos(egui::pos2(10.0, 400.0))
+            .show(ctx, |ui| {
+                ui.label(format!(
+                    "Tree {} ({})",
+                    inspection.tree_index, inspection.species_name
+                ));
+                ui.label("Displacement matrix:");
+                let matrix = &inspection.displacement_matrix;
+                for row in 0..4 {
+                    ui.monospace(format!(
+                        "{:8.3} {:8.3} {:8.3} {:8.3}",
+                        matrix[(row, 0)],
+                        matrix[(row, 1)],
+                        matrix[(row, 2)],
+                        matrix[(row, 3)]
+                    ));
+                }
+                ui.separator();
+                let segment = &inspection.segment;
+                ui.label(format!("Segment: {}", inspection.segment_index));
+                ui.label(format!(
+                    "Symbol: {} (position {} in the derivation)",
+                    inspection.symbol_name, segment.symbol_index
+                ));
+                match &inspection.rule {
+                    Ok(Some(rule)) => ui.label(format!("Rule: {rule}")),
+                    Ok(None) => ui.label("Rule: none, the symbol is part of the axiom"),
+                    Err(e) => ui.colored_label(egui::Color32::RED, format!("Rule: {e}")),
+                };
+                ui.label(format!("Depth: {}", segment.depth));
+                ui.label(format!("Branch order: {}", segment.branch_order));
+            });
+    }
+
+    /// Shows every derivation step of the L-system with the symbols colored by the rule that
+    /// produced them
+    fn ui_derivation_history(
+        lsystem_config: &LSystemConfig,
+        history_length: &mut usize,
+        history_view: &mut Option<HistoryView>,
+        open: &mut bool,
+        ctx: &egui::Context,
+    ) {
+        let view = match history_view {
+            Some(view)
+                if view.lsystem_config == *lsystem_config
+                    && view.history_length == *history_length =>
+            {
+                view
+            }
+            _ => history_view.insert(HistoryView::new(lsystem_config, *history_length)),
+        };
+
+        egui::Window::new("Derivation history")
+            .open(open)
+            .default_pos(egui::pos2(400.0, 10.0))
+            .default_width(500.0)
+            .show(ctx, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    for (i, (predecessor, successor)) in
+                        lsystem_config.production_rules.iter().enumerate()
+                    {
+                        ui.colored_label(
+                            GuiController::rule_color(Some(i)),
+                            format!("{i}: {predecessor} -> {successor}"),
+                        );
+                    }
+                    ui.colored_label(GuiController::rule_color(None), "unchanged");
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (iteration, step) in view.steps.iter().enumerate() {
+                        ui.strong(format!("Iteration {iteration}: {} symbols", step.length));
+                        ui.label(&step.counts);
+                        ui.add(egui::Label::new(step.symbols.clone()).wrap());
+                        if step.hidden > 0 {
+                            ui.horizontal(|ui| {
+                                ui.label(format!("... {} more symbols", step.hidden));
+                                if *history_length < MAX_HISTORY_LENGTH
+                                    && ui.button("Show more").clicked()
+                                {
+                                    *history_length =
+                                        history_length.saturating_mul(2).min(MAX_HISTORY_LENGTH);
+                                }
+                            });
+                        }
+                        ui.separator();
+                    }
+                });
+            });
+    }
+
+    fn rule_color(rule_index: Option<usize>) -> egui::Color32 {
+        rule_index.map_or(egui::Color32::GRAY, |i| RULE_COLORS[i % RULE_COLORS.len()])
+    }
+
+    /// Lists how many times each symbol occurs in the step, in the order of the alphabet
+    fn symbol_counts(step: &HistoryStep, alphabet: &Alphabet) -> String {
+        alphabet
+            .names()
+            .zip(&step.counts)
+            .filter(|(_, count)| **count > 0)
+            .map(|(name, count)| format!("{name}: {count}"))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Lays out the symbols of the step in runs colored by the rule that produced them
+    fn highlighted_step(step: &HistoryStep, alphabet: &Alphabet) -> egui::text::LayoutJob {
+        let mut job = egui::text::LayoutJob::default();
+        let mut append = |text: &str, rule_index| {
+            job.append(
+                text,
+                0.0,
+                egui::TextFormat::simple(
+                    egui::FontId::monospace(12.0),
+                    GuiController::rule_color(rule_index),
+                ),
+            );
+        };
+
+        let mut run = String::new();
+        let mut run_rule = None;
+        for (&symbol, &rule_index) in step.symbols.iter().zip(&step.rules) {
+            if rule_index != run_rule && !run.is_empty() {
+                append(&run, run_rule);
+                run.clear();
+            }
+            run_rule = rule_index;
+            run.push_str(&symbol::written(alphabet.name(symbol)));
+        }
+        if !run.is_empty() {
+            append(&run, run_rule);
+        }
+        job
+    }
+
     fn ui_fractal_height(fractal_height: &mut f32, ui: &mut Ui) {
         ui.label("Fractal Height:");
         ui.add(egui::Slider::new(fractal_height, 0.1..=5.0).text("Fractal Height"));
@@ -220,6 +980,8 @@ impl GuiController {
 
     fn ui_tree_generation_config(
         tree_generation_config: &mut TreeGenerationConfig,
+        placement_error: Option<&String>,
+        layout_edited: bool,
         requires_redraw: &mut bool,
         ui: &mut Ui,
     ) {
@@ -258,12 +1020,244 @@ impl GuiController {
             )
             .text("Z Max"),
         );
+
+        egui::ComboBox::from_label("Placement")
+            .selected_text(format!("{:?}", tree_generation_config.placement_strategy))
+            .show_ui(ui, |ui| {
+                let strategy = &mut tree_generation_config.placement_strategy;
+                ui.selectable_value(strategy, PlacementStrategy::Uniform, "Uniform");
+                ui.selectable_value(strategy, PlacementStrategy::PoissonDisk, "Poisson disk");
+                ui.selectable_value(strategy, PlacementStrategy::JitteredGrid, "Jittered grid");
+                ui.selectable_value(strategy, PlacementStrategy::DensityMap, "Density map");
+            });
+        match tree_generation_config.placement_strategy {
+            PlacementStrategy::PoissonDisk => {
+                ui.add(
+                    egui::Slider::new(&mut tree_generation_config.min_spacing, 0.5..=20.0)
+                        .text("Min spacing"),
+                );
+            }
+            PlacementStrategy::DensityMap => {
+                ui.horizontal(|ui| {
+                    ui.label("Density map:");
+                    ui.text_edit_singleline(&mut tree_generation_config.density_map_path);
+                });
+                if let Some(error) = placement_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            }
+            PlacementStrategy::Uniform | PlacementStrategy::JitteredGrid => {}
+        }
+        ui.horizontal(|ui| {
+            ui.label("Seed:");
+            ui.add(egui::DragValue::new(&mut tree_generation_config.seed));
+        });
+
+        let variation = &mut tree_generation_config.variation;
+        ui.label("Variation:");
+        ui.add(
+            egui::Slider::new(&mut variation.min_scale, 0.25..=variation.max_scale)
+                .text("Min scale"),
+        );
+        ui.add(
+            egui::Slider::new(&mut variation.max_scale, variation.min_scale..=4.0)
+                .text("Max scale"),
+        );
+        ui.add(egui::Slider::new(&mut variation.max_tilt, 0.0..=30.0).text("Max tilt"));
+        ui.add(
+            egui::Slider::new(&mut variation.iteration_jitter, 0..=MAX_ITERATION_JITTER)
+                .text("Iteration jitter"),
+        );
+
+        if layout_edited {
+            ui.label("Trees were edited by hand, changes above apply after regenerating");
+        }
         if ui.add(egui::Button::new("Regenerate trees")).clicked() {
+            tree_generation_config.seed = rand::random();
             *requires_redraw = true;
         }
     }
 
+    fn ui_edit_panel(
+        edit_tool: &mut EditTool,
+        scene_path: &mut String,
+        scene_request: &mut Option<SceneRequest>,
+        scene_status: Option<&Result<String, String>>,
+        ui: &mut Ui,
+    ) {
+        ui.label("Click on the scene to:");
+        ui.horizontal(|ui| {
+            ui.radio_value(edit_tool, EditTool::Inspect, "Inspect");
+            ui.radio_value(edit_tool, EditTool::Plant, "Plant");
+            ui.radio_value(edit_tool, EditTool::Move, "Move");
+            ui.radio_value(edit_tool, EditTool::Rotate, "Rotate");
+            ui.radio_value(edit_tool, EditTool::Delete, "Delete");
+        });
+        match edit_tool {
+            EditTool::Plant => {
+                ui.label("Plants the selected species on the terrain");
+            }
+            EditTool::Move | EditTool::Rotate => {
+                ui.label("Drag a tree to move or rotate it");
+            }
+            EditTool::Inspect | EditTool::Delete => {}
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Scene file:");
+            ui.text_edit_singleline(scene_path)
+                .on_hover_text("Holds the species together with the trees placed in the scene");
+        });
+        ui.horizontal(|ui| {
+            if ui.button("Save scene").clicked() {
+                *scene_request = Some(SceneRequest::Save);
+            }
+            if ui.button("Load scene").clicked() {
+                *scene_request = Some(SceneRequest::Load);
+            }
+        });
+        match scene_status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            None => {}
+        }
+    }
+
+    fn ui_screenshot_panel(
+        settings: &mut ScreenshotSettings,
+        screenshot_request: &mut Option<ScreenshotRequest>,
+        screenshot_status: Option<&Result<String, String>>,
+        ui: &mut Ui,
+    ) {
+        ui.horizontal(|ui| {
+            ui.label("Screenshot directory:");
+            ui.text_edit_singleline(&mut settings.directory);
+        });
+        ui.checkbox(&mut settings.include_gui, "Include the control panel");
+        if ui.button("Save screenshot").on_hover_text("F12").clicked() {
+            *screenshot_request = Some(ScreenshotRequest::Window);
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("High resolution:");
+            ui.add(egui::DragValue::new(&mut settings.width).range(16..=32768));
+            ui.label("x");
+            ui.add(egui::DragValue::new(&mut settings.height).range(16..=32768));
+        });
+        ui.horizontal(|ui| {
+            for (label, width, height) in [
+                ("1080p", 1920, 1080),
+                ("4K", 3840, 2160),
+                ("8K", 7680, 4320),
+            ] {
+                if ui.button(label).clicked() {
+                    (settings.width, settings.height) = (width, height);
+                }
+            }
+        });
+        if ui.button("Save high-resolution screenshot").clicked() {
+            *screenshot_request = Some(ScreenshotRequest::HighResolution);
+        }
+        match screenshot_status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            None => {}
+        }
+    }
+
+    /// Settings of a frame sequence export. `growth_steps` is the number of derivation steps
+    /// the growth animation plays at `growth_speed`.
+    #[allow(clippy::cast_precision_loss)]
+    fn ui_export_panel(
+        settings: &mut ExportSettings,
+        export_request: &mut Option<ExportRequest>,
+        export_progress: Option<(u32, u32)>,
+        export_status: Option<&Result<String, String>>,
+        (growth_steps, growth_speed): (u32, f32),
+        ui: &mut Ui,
+    ) {
+        ui.label("Export frame sequence:");
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut settings.source, ExportSource::Orbit, "Camera orbit");
+            ui.radio_value(
+                &mut settings.source,
+                ExportSource::Growth,
+                "Growth animation",
+            );
+        });
+        ui.horizontal(|ui| {
+            ui.label("Frames:");
+            ui.add(egui::DragValue::new(&mut settings.frames).range(1..=100_000));
+            ui.label("FPS:");
+            ui.add(egui::DragValue::new(&mut settings.fps).range(1.0..=240.0));
+        });
+        if settings.source == ExportSource::Growth {
+            let growth_frames = settings.growth_frames(growth_steps, growth_speed);
+            ui.horizontal(|ui| {
+                ui.label(format!("The plant grows in {growth_frames} frames"));
+                if ui.button("Use").clicked() {
+                    settings.frames = growth_frames;
+                }
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.label("Resolution:");
+            ui.add(egui::DragValue::new(&mut settings.width).range(16..=32768));
+            ui.label("x");
+            ui.add(egui::DragValue::new(&mut settings.height).range(16..=32768));
+        });
+        ui.horizontal(|ui| {
+            ui.label("Frame directory:");
+            ui.text_edit_singleline(&mut settings.directory)
+                .on_hover_text("Frames of earlier exports in this directory are deleted");
+        });
+
+        if let Some((exported, frames)) = export_progress {
+            ui.add(
+                egui::ProgressBar::new(exported as f32 / frames as f32)
+                    .text(format!("Exported {exported} of {frames} frames")),
+            );
+            if ui.button("Cancel export").clicked() {
+                *export_request = Some(ExportRequest::Cancel);
+            }
+        } else if ui.button("Export frames").clicked() {
+            *export_request = Some(ExportRequest::Start);
+        }
+        match export_status {
+            Some(Ok(message)) => {
+                ui.label(message);
+            }
+            Some(Err(error)) => {
+                ui.colored_label(egui::Color32::RED, error);
+            }
+            None => {}
+        }
+    }
+
     fn ui_lsystem_config(
+        lsystem_config: &mut LSystemConfig,
+        preset_selection: &mut PresetSelection,
+        growth_limits: &mut GrowthLimits,
+        parallel_rewriting: &mut bool,
+        show_history: &mut bool,
+        ui: &mut Ui,
+    ) {
+        GuiController::ui_grammar(lsystem_config, preset_selection, ui);
+        ui.checkbox(show_history, "Show derivation history");
+        ui.separator();
+        GuiController::ui_growth_limits(lsystem_config, growth_limits, parallel_rewriting, ui);
+    }
+
+    /// Edits the preset, iterations, angle, axiom and rules of a grammar
+    fn ui_grammar(
         lsystem_config: &mut LSystemConfig,
         preset_selection: &mut PresetSelection,
         ui: &mut Ui,
@@ -291,26 +1285,32 @@ impl GuiController {
         );
         ui.add(egui::Slider::new(&mut lsystem_config.angle, 0.0..=45.0).text("Angle"));
 
+        let diagnostics = validation::validate(lsystem_config);
+
         ui.horizontal(|ui| {
             ui.label("Axiom:");
             if ui.text_edit_singleline(&mut lsystem_config.axiom).changed() {
                 *preset_selection = PresetSelection::Custom;
             }
         });
+        GuiController::ui_diagnostics(
+            diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.location == Location::Axiom),
+            ui,
+        );
 
         ui.label("Production Rules:");
 
         let mut rules_changed = false;
         let mut to_remove = None;
 
-        for (i, (symbol, replacement)) in lsystem_config.production_rules.iter_mut().enumerate() {
+        for (i, (predecessor, replacement)) in
+            lsystem_config.production_rules.iter_mut().enumerate()
+        {
             ui.horizontal(|ui| {
-                let mut symbol_str = symbol.to_string();
                 ui.label(format!("{i}:"));
-                if ui.text_edit_singleline(&mut symbol_str).changed()
-                    && let Some(c) = symbol_str.chars().next()
-                {
-                    *symbol = c;
+                if ui.text_edit_singleline(predecessor).changed() {
                     rules_changed = true;
                 }
                 ui.label("->");
@@ -322,6 +1322,12 @@ impl GuiController {
                     rules_changed = true;
                 }
             });
+            GuiController::ui_diagnostics(
+                diagnostics
+                    .iter()
+                    .filter(|diagnostic| diagnostic.is_for_rule(i)),
+                ui,
+            );
         }
 
         if let Some(idx) = to_remove {
@@ -329,13 +1335,311 @@ impl GuiController {
         }
 
         if ui.button("➕ Add Rule").clicked() {
-            lsystem_config.production_rules.push(('X', "X".to_string()));
+            lsystem_config
+                .production_rules
+                .push(("X".to_string(), "X".to_string()));
             rules_changed = true;
         }
 
         if rules_changed {
             *preset_selection = PresetSelection::Custom;
         }
+
+        if diagnostics.iter().any(Diagnostic::is_error) {
+            ui.colored_label(
+                egui::Color32::RED,
+                "Fix the errors above to regenerate the plant",
+            );
+        }
+    }
+
+    /// Edits variant B of the split view, which shares everything but the grammar with
+    /// the selected species
+    fn ui_comparison(
+        comparison: &mut ComparisonConfig,
+        lsystem_config: &LSystemConfig,
+        preset_selection: PresetSelection,
+        shows_split_view: bool,
+        ui: &mut Ui,
+    ) {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut comparison.enabled, "Split view (A/B)");
+            if ui.button("Copy A → B").clicked() {
+                comparison.lsystem_config = lsystem_config.clone();
+                comparison.preset_selection = preset_selection;
+            }
+        });
+        if comparison.enabled && !shows_split_view {
+            ui.label("Hidden while the plants grow, variant B is only generated fully grown");
+        }
+        if comparison.enabled {
+            ui.collapsing("Variant B", |ui| {
+                ui.push_id("variant_b", |ui| {
+                    GuiController::ui_grammar(
+                        &mut comparison.lsystem_config,
+                        &mut comparison.preset_selection,
+                        ui,
+                    );
+                });
+            });
+        }
+    }
+
+    /// Names the halves of the split view
+    fn ui_comparison_labels(species_name: &str, ctx: &egui::Context) {
+        let screen = ctx.screen_rect();
+        let labels = [
+            ("comparison_a", screen.left(), format!("A: {species_name}")),
+            ("comparison_b", screen.center().x, "B".to_string()),
+        ];
+        for (id, x, text) in labels {
+            egui::Area::new(egui::Id::new(id))
+                .fixed_pos(egui::pos2(x + 10.0, screen.bottom() - 40.0))
+                .interactable(false)
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.strong(text);
+                    });
+                });
+        }
+    }
+
+    fn ui_plant_stats(stats: Option<&PlantStats>, ui: &mut Ui) {
+        ui.collapsing("Plant statistics", |ui| {
+            let Some(stats) = stats else {
+                ui.label("The plant has not been generated yet");
+                return;
+            };
+            ui.label(format!("Derivation length: {}", stats.derivation_length));
+            ui.label(format!("Segments: {}", stats.segments));
+            ui.label(format!("Branch points: {}", stats.branch_points));
+            ui.label(format!("Max bracket depth: {}", stats.max_branch_depth));
+            ui.label(format!(
+                "Total branch length: {:.2}",
+                stats.total_branch_length
+            ));
+            ui.label(format!(
+                "Bounding box: {:.2} x {:.2} x {:.2}",
+                stats.dimensions.x, stats.dimensions.y, stats.dimensions.z
+            ));
+            ui.label(format!("Scale factor: {:.4}", stats.scale_factor));
+            ui.label(format!("Triangles per tree: {}", stats.triangles));
+        });
+    }
+
+    /// Shows the predicted size of the plant, warning before it exceeds the limits
+    fn ui_growth_limits(
+        lsystem_config: &LSystemConfig,
+        growth_limits: &mut GrowthLimits,
+        parallel_rewriting: &mut bool,
+        ui: &mut Ui,
+    ) {
+        ui.collapsing("Growth limits", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Max symbols:");
+                ui.add(
+                    egui::DragValue::new(&mut growth_limits.max_length)
+                        .range(1..=100_000_000)
+                        .speed(1000),
+                );
+            });
+            ui.horizontal(|ui| {
+                ui.label("Max segments:");
+                ui.add(
+                    egui::DragValue::new(&mut growth_limits.max_segments)
+                        .range(1..=10_000_000)
+                        .speed(100),
+                );
+            });
+            ui.checkbox(parallel_rewriting, "Rewrite large plants on all cores")
+                .on_hover_text(format!(
+                    "Derives plants of more than {PARALLEL_DERIVATION_LENGTH} symbols in \
+                     parallel, which holds the whole derivation in memory instead of \
+                     streaming it"
+                ));
+        });
+
+        let lsystem = lsystem_config.to_lsystem().with_limits(*growth_limits);
+        let n_iterations = lsystem_config.n_iterations;
+        if let Some(estimate) = lsystem.estimate_growth(n_iterations).last() {
+            ui.label(format!(
+                "Predicted size: {} symbols, {} segments",
+                estimate.length, estimate.segments
+            ));
+        }
+        if let Err(e) = lsystem.check_growth(n_iterations) {
+            ui.colored_label(egui::Color32::RED, e);
+            ui.colored_label(
+                egui::Color32::RED,
+                "Lower the number of iterations or raise the limits to regenerate the plant",
+            );
+        }
+    }
+
+    /// Lists diagnostics below the axiom or rule they refer to
+    fn ui_diagnostics<'a>(diagnostics: impl Iterator<Item = &'a Diagnostic>, ui: &mut Ui) {
+        for diagnostic in diagnostics {
+            let color = match diagnostic.severity {
+                Severity::Error => egui::Color32::RED,
+                Severity::Warning => egui::Color32::YELLOW,
+            };
+            let text = match (diagnostic.location, diagnostic.position) {
+                (Location::Predecessor(_), _) => format!("  predecessor: {}", diagnostic.message),
+                (_, Some(position)) => format!("  col {}: {}", position + 1, diagnostic.message),
+                (_, None) => format!("  {}", diagnostic.message),
+            };
+            ui.colored_label(color, text);
+        }
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn ui_growth_animation(
+        growth_animation: &mut GrowthAnimation,
+        growth_error: Option<&String>,
+        lsystem_config: &mut LSystemConfig,
+        n_iterations: u32,
+        ui: &mut Ui,
+    ) {
+        ui.checkbox(&mut growth_animation.enabled, "Growth animation");
+        if !growth_animation.enabled {
+            return;
+        }
+        if let Some(error) = growth_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        ui.horizontal(|ui| {
+            let play_label = if growth_animation.playing {
+                "⏸ Pause"
+            } else {
+                "▶ Play"
+            };
+            if ui.button(play_label).clicked() {
+                if !growth_animation.playing && growth_animation.time >= n_iterations as f32 {
+                    growth_animation.restart();
+                } else {
+                    growth_animation.playing = !growth_animation.playing;
+                }
+            }
+            if ui.button("⏮ Restart").clicked() {
+                growth_animation.restart();
+            }
+        });
+
+        let timeline = ui.add(
+            egui::Slider::new(&mut growth_animation.time, 0.0..=n_iterations as f32).text("Step"),
+        );
+        if timeline.dragged() {
+            growth_animation.playing = false;
+        }
+        ui.add(egui::Slider::new(&mut growth_animation.speed, 0.1..=5.0).text("Speed (steps/s)"));
+
+        ui.checkbox(&mut growth_animation.timed, "Continuous (timed) growth");
+        if growth_animation.timed {
+            Self::ui_timed_growth(growth_animation, lsystem_config, ui);
+        }
+    }
+
+    fn ui_timed_growth(
+        growth_animation: &mut GrowthAnimation,
+        lsystem_config: &mut LSystemConfig,
+        ui: &mut Ui,
+    ) {
+        egui::ComboBox::from_label("Growth function")
+            .selected_text(format!("{:?}", growth_animation.growth_function))
+            .show_ui(ui, |ui| {
+                let growth_function = &mut growth_animation.growth_function;
+                ui.selectable_value(growth_function, GrowthFunction::Linear, "Linear");
+                ui.selectable_value(growth_function, GrowthFunction::Smoothstep, "Smoothstep");
+            });
+
+        ui.label("Terminal ages:");
+        // Rewritten symbols and segments, which grow even if they are never rewritten
+        let mut symbols: Vec<String> = lsystem_config
+            .production_rules
+            .iter()
+            .map(|(symbol, _)| symbol.clone())
+            .collect();
+        let lsystem = lsystem_config.to_lsystem();
+        for name in lsystem.alphabet().names() {
+            if TurtleInterpreter::is_segment(name)
+                && !symbols.iter().any(|symbol| symbol::name_of(symbol) == name)
+            {
+                symbols.push(symbol::written(name).into_owned());
+            }
+        }
+        for symbol in symbols {
+            let terminal_ages = &mut lsystem_config.terminal_ages;
+            let mut terminal_age = terminal_ages
+                .iter()
+                .find(|(s, _)| *s == symbol)
+                .map_or(DEFAULT_TERMINAL_AGE, |(_, age)| *age);
+
+            if ui
+                .add(egui::Slider::new(&mut terminal_age, 0.1..=3.0).text(&symbol))
+                .changed()
+            {
+                terminal_ages.retain(|(s, _)| *s != symbol);
+                terminal_ages.push((symbol, terminal_age));
+            }
+        }
+    }
+
+    fn ui_wind_panel(wind_parameters: &mut WindParameters, ui: &mut Ui) {
+        ui.label("Wind:");
+        ui.add(egui::Slider::new(&mut wind_parameters.strength, 0.0..=1.0).text("Strength"));
+        ui.add(
+            egui::Slider::new(&mut wind_parameters.direction, 0.0..=360.0)
+                .text("Direction")
+                .suffix("°"),
+        );
+        ui.add(
+            egui::Slider::new(&mut wind_parameters.gust_frequency, 0.05..=3.0)
+                .text("Gust frequency"),
+        );
+    }
+
+    fn ui_terrain_panel(
+        terrain_config: &mut TerrainConfig,
+        terrain_error: Option<&String>,
+        ui: &mut Ui,
+    ) {
+        ui.label("Terrain:");
+        egui::ComboBox::from_label("Terrain source")
+            .selected_text(format!("{:?}", terrain_config.source))
+            .show_ui(ui, |ui| {
+                let source = &mut terrain_config.source;
+                ui.selectable_value(source, TerrainSource::Flat, "Flat");
+                ui.selectable_value(source, TerrainSource::Noise, "Noise");
+                ui.selectable_value(source, TerrainSource::Heightmap, "Heightmap");
+            });
+
+        match terrain_config.source {
+            TerrainSource::Flat => return,
+            TerrainSource::Noise => {
+                ui.horizontal(|ui| {
+                    ui.label("Seed:");
+                    ui.add(egui::DragValue::new(&mut terrain_config.seed));
+                });
+                ui.add(
+                    egui::Slider::new(&mut terrain_config.noise_frequency, 0.005..=0.2)
+                        .logarithmic(true)
+                        .text("Frequency"),
+                );
+                ui.add(egui::Slider::new(&mut terrain_config.noise_octaves, 1..=8).text("Octaves"));
+            }
+            TerrainSource::Heightmap => {
+                ui.horizontal(|ui| {
+                    ui.label("Heightmap:");
+                    ui.text_edit_singleline(&mut terrain_config.heightmap_path);
+                });
+                if let Some(error) = terrain_error {
+                    ui.colored_label(egui::Color32::RED, error);
+                }
+            }
+        }
+        ui.add(egui::Slider::new(&mut terrain_config.max_height, 0.0..=20.0).text("Max height"));
+        ui.add(egui::Slider::new(&mut terrain_config.resolution, 8..=256).text("Resolution"));
     }
 
     fn ui_color_panel(
@@ -350,30 +1654,142 @@ impl GuiController {
         ui.color_edit_button_rgb(interpolation_color_high);
     }
 
-    pub fn draw(&mut self, window: &Window, display: &Display<WindowSurface>, frame: &mut Frame) {
-        let model_selection = &mut self.model_selection;
+    #[allow(clippy::too_many_lines)]
+    pub fn draw(
+        &mut self,
+        window: &Window,
+        display: &Display<WindowSurface>,
+        frame: &mut Frame,
+        show_control_panel: bool,
+        stats: &RenderStats,
+    ) {
+        let max_iterations = self.max_iterations();
+        let shows_split_view = self.shows_split_view();
         let shading_mode = &mut self.shading_mode;
-        let lsystem_config = &mut self.lsystem_config;
-        let preset_selection = &mut self.preset_selection;
-        let color_low = &mut self.interpolation_color_low;
-        let color_high = &mut self.interpolation_color_high;
 
         self.egui_glium.run(window, |ctx| {
+            if self.show_stats {
+                GuiController::ui_stats_overlay(stats, ctx);
+            }
+            if shows_split_view {
+                GuiController::ui_comparison_labels(&self.species[self.selected_species].name, ctx);
+            }
+            if !show_control_panel {
+                return;
+            }
+            if let Some(inspection) = &self.inspection {
+                GuiController::ui_inspector(inspection, ctx);
+            }
+            if self.show_history {
+                GuiController::ui_derivation_history(
+                    &self.species[self.selected_species].lsystem_config,
+                    &mut self.history_length,
+                    &mut self.history_view,
+                    &mut self.show_history,
+                    ctx,
+                );
+            }
+
             egui::Window::new("Control panel").show(ctx, |ui| {
-                GuiController::ui_control_panel(model_selection, shading_mode, ui);
+                GuiController::ui_undo_panel(&self.undo_history, &mut self.undo_request, ui);
+                ui.separator();
+                GuiController::ui_species_list(&mut self.species, &mut self.selected_species, ui);
+                if let Some(progress) = self.generation_progress {
+                    ui.add(
+                        egui::ProgressBar::new(progress)
+                            .show_percentage()
+                            .text("Generating plants"),
+                    );
+                }
+                ui.separator();
+                let species = &mut self.species[self.selected_species];
+                let lsystem_config = &mut species.lsystem_config;
+                GuiController::ui_control_panel(&mut species.model_selection, shading_mode, ui);
+                GuiController::ui_render_options(
+                    &mut self.frustum_culling,
+                    &mut self.show_stats,
+                    &mut self.lod_settings,
+                    &mut self.texture_tiling,
+                    &mut self.foliage_settings,
+                    ui,
+                );
                 GuiController::ui_fractal_height(&mut lsystem_config.fractal_height, ui);
                 ui.separator();
-                GuiController::ui_lsystem_config(lsystem_config, preset_selection, ui);
+                GuiController::ui_lsystem_config(
+                    lsystem_config,
+                    &mut species.preset_selection,
+                    &mut self.growth_limits,
+                    &mut self.parallel_rewriting,
+                    &mut self.show_history,
+                    ui,
+                );
+                GuiController::ui_plant_stats(self.plant_stats.get(self.selected_species), ui);
+                GuiController::ui_comparison(
+                    &mut self.comparison,
+                    lsystem_config,
+                    species.preset_selection,
+                    shows_split_view,
+                    ui,
+                );
+                ui.separator();
+                GuiController::ui_growth_animation(
+                    &mut self.growth_animation,
+                    self.growth_error.as_ref(),
+                    lsystem_config,
+                    max_iterations,
+                    ui,
+                );
+                ui.separator();
+                GuiController::ui_color_panel(
+                    &mut species.interpolation_color_low,
+                    &mut species.interpolation_color_high,
+                    ui,
+                );
+                ui.separator();
+                GuiController::ui_wind_panel(&mut self.wind_parameters, ui);
                 ui.separator();
-                GuiController::ui_color_panel(color_low, color_high, ui);
+                GuiController::ui_terrain_panel(
+                    &mut self.terrain_config,
+                    self.terrain_error.as_ref(),
+                    ui,
+                );
                 ui.separator();
                 GuiController::ui_tree_generation_config(
                     &mut self.tree_generation_config,
+                    self.placement_error.as_ref(),
+                    self.layout_edited,
                     &mut self.requires_tree_regeneration,
                     ui,
                 );
+                ui.separator();
+                GuiController::ui_edit_panel(
+                    &mut self.edit_tool,
+                    &mut self.scene_path,
+                    &mut self.scene_request,
+                    self.scene_status.as_ref(),
+                    ui,
+                );
+                ui.separator();
+                GuiController::ui_screenshot_panel(
+                    &mut self.screenshot_settings,
+                    &mut self.screenshot_request,
+                    self.screenshot_status.as_ref(),
+                    ui,
+                );
+                ui.separator();
+                GuiController::ui_export_panel(
+                    &mut self.export_settings,
+                    &mut self.export_request,
+                    self.export_progress,
+                    self.export_status.as_ref(),
+                    (max_iterations, self.growth_animation.speed),
+                    ui,
+                );
             });
         });
+        if show_control_panel {
+            self.update_undo_history();
+        }
         self.egui_glium.paint(display, frame);
     }
 }
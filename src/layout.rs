@@ -0,0 +1,227 @@
+use crate::placement::MAX_ITERATION_JITTER;
+use glm::Mat4;
+use std::fmt::Write;
+
+/// Comment heading the trees in a scene file
+pub const HEADER: &str =
+    "# species, iteration offset and displacement matrix in column-major order, one tree per line";
+
+/// Trees of a scene, in the order they are stored in the scene
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeLayout {
+    pub displacement_matrices: Vec<Mat4>,
+    pub tree_species: Vec<usize>,
+    /// Number of derivation steps every tree lacks compared to its species
+    pub iteration_offsets: Vec<u32>,
+}
+
+impl TreeLayout {
+    /// Writes one tree per line, following `HEADER`
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{HEADER}\n");
+        for ((matrix, species), iteration_offset) in self
+            .displacement_matrices
+            .iter()
+            .zip(&self.tree_species)
+            .zip(&self.iteration_offsets)
+        {
+            let _ = write!(text, "{species} {iteration_offset}");
+            for value in matrix.iter() {
+                let _ = write!(text, " {value}");
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Appends the tree written on a line by `to_text`
+    pub fn push_line(&mut self, line: &str) -> Result<(), String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 18 {
+            return Err(format!("expected 18 values, found {}", fields.len()));
+        }
+        let species = fields[0]
+            .parse()
+            .map_err(|e| format!("invalid species: {e}"))?;
+        let iteration_offset = fields[1]
+            .parse()
+            .map_err(|e| format!("invalid iteration offset: {e}"))?;
+        let values = fields[2..]
+            .iter()
+            .map(|field| field.parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid matrix: {e}"))?;
+
+        self.displacement_matrices
+            .push(Mat4::from_column_slice(&values));
+        self.tree_species.push(species);
+        self.iteration_offsets.push(iteration_offset);
+        Ok(())
+    }
+
+    /// Returns an error for the first tree of a species past `n_species` or lacking more
+    /// derivation steps than placed trees may
+    pub fn validate(&self, n_species: usize) -> Result<(), String> {
+        for (tree_index, (&species, &iteration_offset)) in self
+            .tree_species
+            .iter()
+            .zip(&self.iteration_offsets)
+            .enumerate()
+        {
+            if species >= n_species {
+                return Err(format!(
+                    "Tree {tree_index} is of species {species}, but there are only {n_species}"
+                ));
+            }
+            if iteration_offset > MAX_ITERATION_JITTER {
+                return Err(format!(
+                    "Tree {tree_index} lacks {iteration_offset} derivation steps, more than the \
+                     limit of {MAX_ITERATION_JITTER}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves every tree to the species at the index given for its own, dropping the trees of
+    /// species that map to `None` or past the end
+    pub fn remap_species(&mut self, remapping: &[Option<usize>]) {
+        let mut kept = Self::default();
+        for ((&matrix, &species), &iteration_offset) in self
+            .displacement_matrices
+            .iter()
+            .zip(&self.tree_species)
+            .zip(&self.iteration_offsets)
+        {
+            if let Some(&Some(species)) = remapping.get(species) {
+                kept.displacement_matrices.push(matrix);
+                kept.tree_species.push(species);
+                kept.iteration_offsets.push(iteration_offset);
+            }
+        }
+        *self = kept;
+    }
+}
+
+/// Index in `current` of every element of `previous`, `None` for removed ones. Lists of the
+/// same length are edited in place. Otherwise elements equal in both keep their order, and
+/// between them the remaining ones are paired in order, extra previous ones being removed.
+pub fn remapping<T: PartialEq>(previous: &[T], current: &[T]) -> Vec<Option<usize>> {
+    if previous.len() == current.len() {
+        return (0..previous.len()).map(Some).collect();
+    }
+
+    // Longest common subsequence, `lengths[i][j]` of `previous[i..]` and `current[j..]`
+    let mut lengths = vec![vec![0_usize; current.len() + 1]; previous.len() + 1];
+    for i in (0..previous.len()).rev() {
+        for j in (0..current.len()).rev() {
+            lengths[i][j] = if previous[i] == current[j] {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut result = vec![None; previous.len()];
+    let (mut i, mut j) = (0, 0);
+    // Start of the unmatched elements since the last common one
+    let (mut gap_i, mut gap_j) = (0, 0);
+    while i < previous.len() && j < current.len() {
+        if previous[i] == current[j] {
+            for (offset, mapped) in result[gap_i..i].iter_mut().enumerate() {
+                *mapped = (gap_j + offset < j).then_some(gap_j + offset);
+            }
+            result[i] = Some(j);
+            i += 1;
+            j += 1;
+            (gap_i, gap_j) = (i, j);
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    for (offset, mapped) in result[gap_i..].iter_mut().enumerate() {
+        *mapped = (gap_j + offset < current.len()).then_some(gap_j + offset);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_trees() -> TreeLayout {
+        TreeLayout {
+            displacement_matrices: vec![
+                glm::translation(&glm::vec3(1.5, -0.25, 3.0)),
+                glm::rotation(0.3, &glm::vec3(0.0, 1.0, 0.0))
+                    * glm::scaling(&glm::vec3(2.0, 2.0, 2.0)),
+            ],
+            tree_species: vec![0, 2],
+            iteration_offsets: vec![1, 0],
+        }
+    }
+
+    #[test]
+    fn test_layout_round_trip() {
+        let layout = two_trees();
+        let mut read = TreeLayout::default();
+        for line in layout.to_text().lines().skip(1) {
+            read.push_line(line).unwrap();
+        }
+        assert_eq!(read, layout);
+    }
+
+    #[test]
+    fn test_invalid_layout() {
+        let mut layout = TreeLayout::default();
+        assert!(layout.push_line("0 0 1 2 3").is_err());
+        assert!(
+            layout
+                .push_line("x 0 1 0 0 0 0 1 0 0 0 0 1 0 0 0 0 1")
+                .is_err()
+        );
+
+        let layout = two_trees();
+        assert!(layout.validate(3).is_ok());
+        assert!(layout.validate(2).unwrap_err().starts_with("Tree 1"));
+        let too_young = TreeLayout {
+            iteration_offsets: vec![MAX_ITERATION_JITTER + 1, 0],
+            ..layout
+        };
+        assert!(too_young.validate(3).unwrap_err().starts_with("Tree 0"));
+    }
+
+    #[test]
+    fn test_remap_species() {
+        let mut layout = two_trees();
+        layout.remap_species(&[Some(1), Some(0), None]);
+        assert_eq!(layout.tree_species, [1]);
+        assert_eq!(layout.iteration_offsets, [1]);
+
+        let mut layout = two_trees();
+        layout.remap_species(&[Some(0)]);
+        assert_eq!(layout.tree_species, [0]);
+    }
+
+    #[test]
+    fn test_remapping() {
+        // Removed in the middle, the following ones move up
+        assert_eq!(
+            remapping(&['a', 'b', 'c'], &['a', 'c']),
+            [Some(0), None, Some(1)]
+        );
+        // Appended
+        assert_eq!(remapping(&['a', 'b'], &['a', 'b', 'a']), [Some(0), Some(1)]);
+        // Edited in place
+        assert_eq!(remapping(&['a', 'b'], &['a', 'x']), [Some(0), Some(1)]);
+        // Removed and another one edited, paired in order between equal ones
+        assert_eq!(
+            remapping(&['a', 'b', 'c', 'd'], &['a', 'x', 'd']),
+            [Some(0), Some(1), None, Some(2)]
+        );
+        assert_eq!(remapping(&['a', 'b'], &['b']), [None, Some(0)]);
+    }
+}
@@ -0,0 +1,74 @@
+/// Number of levels of detail of a plant, each using one derivation step less than the previous
+pub const MAX_LOD_LEVELS: u32 = 3;
+/// Distance from the camera beyond which trees switch to the first coarser level, at bias 1
+const BASE_LOD_DISTANCE: f32 = 15.0;
+/// Relative margin around level thresholds that prevents trees from flickering between levels
+const LOD_HYSTERESIS: f32 = 0.1;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodSettings {
+    pub enabled: bool,
+    /// Multiplier of switching distances, higher values keep more detail
+    pub bias: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bias: 1.0,
+        }
+    }
+}
+
+/// Distance beyond which `level` (or a coarser one) is used
+fn lod_threshold(level: usize, bias: f32) -> f32 {
+    let exponent = i32::try_from(level).unwrap_or(i32::MAX) - 1;
+    BASE_LOD_DISTANCE * bias * 2_f32.powi(exponent)
+}
+
+/// Returns the level of detail for a tree at `distance`, starting from its current level.
+/// A tree only changes level once it is past the threshold by the hysteresis margin.
+pub fn select_lod_level(current: usize, distance: f32, bias: f32, n_levels: usize) -> usize {
+    if n_levels == 0 {
+        return 0;
+    }
+
+    let mut level = current.min(n_levels - 1);
+    while level + 1 < n_levels && distance > lod_threshold(level + 1, bias) * (1.0 + LOD_HYSTERESIS)
+    {
+        level += 1;
+    }
+    while level > 0 && distance < lod_threshold(level, bias) * (1.0 - LOD_HYSTERESIS) {
+        level -= 1;
+    }
+    level
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_lod_level_by_distance() {
+        assert_eq!(select_lod_level(0, 5.0, 1.0, 3), 0);
+        assert_eq!(select_lod_level(0, 20.0, 1.0, 3), 1);
+        assert_eq!(select_lod_level(0, 100.0, 1.0, 3), 2);
+        assert_eq!(select_lod_level(2, 5.0, 1.0, 3), 0);
+        assert_eq!(select_lod_level(0, 100.0, 1.0, 1), 0);
+    }
+
+    #[test]
+    fn test_select_lod_level_hysteresis() {
+        let threshold = lod_threshold(1, 1.0);
+        assert_eq!(select_lod_level(0, threshold * 1.05, 1.0, 3), 0);
+        assert_eq!(select_lod_level(1, threshold * 1.05, 1.0, 3), 1);
+        assert_eq!(select_lod_level(1, threshold * 0.95, 1.0, 3), 1);
+        assert_eq!(select_lod_level(1, threshold * 0.85, 1.0, 3), 0);
+    }
+
+    #[test]
+    fn test_bias_keeps_more_detail() {
+        assert_eq!(select_lod_level(0, 20.0, 2.0, 3), 0);
+    }
+}
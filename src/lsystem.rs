@@ -1,37 +1,594 @@
-use std::collections::HashMap;
+use crate::symbol::{self, Alphabet, SymbolId};
+use crate::turtle::TurtleInterpreter;
+use rayon::prelude::*;
+
+/// Terminal age of symbols that have a production or draw a segment, but no explicitly
+/// configured terminal age
+pub const DEFAULT_TERMINAL_AGE: f32 = 1.0;
+/// Lower bound on terminal ages, guards `derive_at` against firing productions endlessly
+const MIN_TERMINAL_AGE: f32 = 0.01;
+/// Length of the pieces a derivation step is split into for parallel rewriting, shorter steps
+/// are rewritten on a single thread
+const PARALLEL_CHUNK_LENGTH: usize = 64 * 1024;
 
 pub struct LSystem {
-    axiom: String,
-    production_rules: HashMap<char, String>,
+    alphabet: Alphabet,
+    axiom: Vec<SymbolId>,
+    /// Successor of every symbol of the alphabet, indexed by its id
+    production_rules: Vec<Option<Vec<SymbolId>>>,
+    /// Index of the rule rewriting every symbol of the alphabet, in the order rules were given
+    rule_indices: Vec<Option<usize>>,
+    /// Configured terminal age of every symbol of the alphabet, indexed by its id
+    terminal_ages: Vec<Option<f32>>,
+    /// Whether every symbol of the alphabet draws a segment, indexed by its id
+    is_segment: Vec<bool>,
+    limits: GrowthLimits,
+}
+
+/// Bounds on derivations, guarding against rules that grow exponentially
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrowthLimits {
+    /// Maximum number of symbols of any derivation step
+    pub max_length: u64,
+    /// Maximum number of segments drawn from any derivation step
+    pub max_segments: u64,
+}
+
+impl Default for GrowthLimits {
+    fn default() -> Self {
+        Self {
+            max_length: 5_000_000,
+            max_segments: 100_000,
+        }
+    }
+}
+
+/// Size of a derivation step, known without performing the derivation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrowthEstimate {
+    pub length: u64,
+    pub segments: u64,
+}
+
+/// A derivation step as shown in the derivation history, see `LSystem::history`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryStep {
+    /// Leading symbols of the step, at most as many as requested
+    pub symbols: Vec<SymbolId>,
+    /// Index of the rule that produced each of `symbols` in this step, `None` for symbols
+    /// carried over unchanged and for the axiom
+    pub rules: Vec<Option<usize>>,
+    /// Occurrences of every symbol of the alphabet in the whole step, indexed by its id
+    pub counts: Vec<u64>,
+    /// Number of symbols of the whole step
+    pub length: u64,
+}
+
+/// A symbol of a timed L-system (ABOP ch. 6) together with its age
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub symbol: SymbolId,
+    pub age: f32,
+    /// Age at which the production of this module fires, or at which a segment without a
+    /// production reaches its full length. `None` for other symbols without a production.
+    pub terminal_age: Option<f32>,
+    /// Whether the module continues a fully grown one (an axiom symbol, or the first occurrence
+    /// of the predecessor in its successor), in which case it is drawn at full length
+    pub mature: bool,
+    /// Index of the rule whose successor created the module, `None` for the axiom
+    pub rule: Option<usize>,
+}
+
+/// How far a plant was grown when it was derived
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GrowthStage {
+    /// Number of derivation steps, see `LSystem::derive`
+    Iterations(u32),
+    /// Time of a timed derivation, see `LSystem::derive_at`
+    Time(f32),
+}
+
+/// Maps the relative age of a module (age / terminal age) to the length of its segment
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GrowthFunction {
+    Linear,
+    #[default]
+    Smoothstep,
+}
+
+impl GrowthFunction {
+    pub fn apply(self, relative_age: f32) -> f32 {
+        let x = relative_age.clamp(0.0, 1.0);
+        match self {
+            GrowthFunction::Linear => x,
+            GrowthFunction::Smoothstep => x * x * (3.0 - 2.0 * x),
+        }
+    }
+}
+
+impl Module {
+    pub fn length(&self, growth_function: GrowthFunction) -> f32 {
+        match self.terminal_age {
+            Some(terminal_age) if !self.mature => growth_function.apply(self.age / terminal_age),
+            _ => 1.0,
+        }
+    }
 }
 
 impl LSystem {
-    pub fn new(axiom: &str, production_rules: HashMap<char, String>) -> Self {
+    /// Builds the L-system from the axiom and `(predecessor, successor)` rules, whose symbols
+    /// are split with `symbol::tokenize`. A later rule for the same predecessor replaces an
+    /// earlier one.
+    pub fn new<'a>(
+        axiom: &str,
+        production_rules: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Self {
+        let mut alphabet = Alphabet::default();
+        let axiom = alphabet.parse(axiom);
+        let rules: Vec<(SymbolId, Vec<SymbolId>)> = production_rules
+            .into_iter()
+            .map(|(predecessor, successor)| {
+                (
+                    alphabet.parse_symbol(predecessor),
+                    alphabet.parse(successor),
+                )
+            })
+            .collect();
+
+        let mut production_rules = vec![None; alphabet.len()];
+        let mut rule_indices = vec![None; alphabet.len()];
+        for (rule_index, (predecessor, successor)) in rules.into_iter().enumerate() {
+            production_rules[predecessor.index()] = Some(successor);
+            rule_indices[predecessor.index()] = Some(rule_index);
+        }
         Self {
-            axiom: axiom.to_string(),
+            terminal_ages: vec![None; alphabet.len()],
+            is_segment: alphabet
+                .names()
+                .map(TurtleInterpreter::is_segment)
+                .collect(),
+            alphabet,
+            axiom,
             production_rules,
+            rule_indices,
+            limits: GrowthLimits::default(),
         }
     }
 
-    pub fn generate(&self, n_iterations: u32) -> String {
-        let mut s = self.axiom.clone();
+    /// Names of the symbols the derivations are made of
+    pub fn alphabet(&self) -> &Alphabet {
+        &self.alphabet
+    }
+
+    pub fn with_limits(mut self, limits: GrowthLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets the terminal ages of symbols used by `derive_at`, by symbol written as in the rules.
+    /// Symbols with a production and segments that are missing from the list use
+    /// `DEFAULT_TERMINAL_AGE`.
+    pub fn with_terminal_ages<'a>(
+        mut self,
+        terminal_ages: impl IntoIterator<Item = (&'a str, f32)>,
+    ) -> Self {
+        for (name, terminal_age) in terminal_ages {
+            if let Some(symbol) = self.alphabet.get(symbol::name_of(name)) {
+                self.terminal_ages[symbol.index()] = Some(terminal_age);
+            }
+        }
+        self
+    }
+
+    fn successor(&self, symbol: SymbolId) -> Option<&[SymbolId]> {
+        self.production_rules[symbol.index()].as_deref()
+    }
+
+    /// Derives the state of a timed DOL-system at time `t`. Every module ages with time and its
+    /// production fires when it reaches its terminal age, creating successors of age zero.
+    /// With the default terminal ages, the symbols at integer `t` match `derive(t)`.
+    /// Returns an error as soon as the modules derived so far exceed the limits.
+    pub fn derive_at(&self, t: f32) -> Result<Vec<Module>, String> {
+        let mut modules = Vec::new();
+        let mut segments = 0_u64;
+
+        // Modules paired with the time they still have to age, the next one on top
+        let mut stack: Vec<(Module, f32)> = self
+            .axiom
+            .iter()
+            .rev()
+            .map(|&symbol| (self.new_module(symbol, true, None), t.max(0.0)))
+            .collect();
+        while let Some((mut module, time)) = stack.pop() {
+            if let Some((time_to_production, replacement)) = self.production_of(&module)
+                && time >= time_to_production
+            {
+                let continuation = replacement
+                    .iter()
+                    .position(|&symbol| symbol == module.symbol);
+                let rule = self.rule_indices[module.symbol.index()];
+                for (index, &symbol) in replacement.iter().enumerate().rev() {
+                    let successor = self.new_module(symbol, continuation == Some(index), rule);
+                    stack.push((successor, time - time_to_production));
+                }
+                continue;
+            }
+
+            module.age += time;
+            if self.is_segment[module.symbol.index()] {
+                segments += 1;
+            }
+            modules.push(module);
+            self.check_timed_growth(t, modules.len() as u64, segments)?;
+        }
+        Ok(modules)
+    }
+
+    /// Time until the production of the module fires and its successor, if it has one
+    fn production_of(&self, module: &Module) -> Option<(f32, &[SymbolId])> {
+        let terminal_age = module.terminal_age?;
+        let replacement = self.successor(module.symbol)?;
+        Some((terminal_age - module.age, replacement))
+    }
+
+    fn check_timed_growth(&self, t: f32, length: u64, segments: u64) -> Result<(), String> {
+        if length > self.limits.max_length {
+            return Err(format!(
+                "At time {t:.2} the plant would have more than the limit of {} symbols",
+                self.limits.max_length
+            ));
+        }
+        if segments > self.limits.max_segments {
+            return Err(format!(
+                "At time {t:.2} the plant would have more than the limit of {} segments",
+                self.limits.max_segments
+            ));
+        }
+        Ok(())
+    }
+
+    /// Smallest terminal age of the symbols with a production, the time the fastest of them
+    /// takes for one derivation step. `DEFAULT_TERMINAL_AGE` if no symbol has a production.
+    pub fn min_terminal_age(&self) -> f32 {
+        let min_terminal_age = (0..self.alphabet.len())
+            .filter(|&index| self.production_rules[index].is_some())
+            .filter_map(|index| self.terminal_age_at(index))
+            .fold(f32::INFINITY, f32::min);
+        if min_terminal_age.is_infinite() {
+            DEFAULT_TERMINAL_AGE
+        } else {
+            min_terminal_age
+        }
+    }
+
+    /// Segments grow over their terminal age even if they are never rewritten, as every module
+    /// of an ABOP timed system has a growth function
+    fn terminal_age_at(&self, index: usize) -> Option<f32> {
+        (self.production_rules[index].is_some() || self.is_segment[index]).then(|| {
+            self.terminal_ages[index]
+                .unwrap_or(DEFAULT_TERMINAL_AGE)
+                .max(MIN_TERMINAL_AGE)
+        })
+    }
+
+    fn new_module(&self, symbol: SymbolId, mature: bool, rule: Option<usize>) -> Module {
+        Module {
+            symbol,
+            age: 0.0,
+            terminal_age: self.terminal_age_at(symbol.index()),
+            mature,
+            rule,
+        }
+    }
+
+    /// Index of the rule whose successor created the symbol at `symbol_index` of the derivation
+    /// at the growth stage. Symbols carried over unchanged keep the rule that created them.
+    /// `None` for symbols of the axiom and for indices past the end of the derivation.
+    pub fn rule_of_symbol(
+        &self,
+        stage: GrowthStage,
+        symbol_index: usize,
+    ) -> Result<Option<usize>, String> {
+        match stage {
+            GrowthStage::Iterations(n_iterations) => {
+                Ok(self.rule_after_iterations(n_iterations, symbol_index))
+            }
+            GrowthStage::Time(t) => Ok(self
+                .derive_at(t)?
+                .get(symbol_index)
+                .and_then(|module| module.rule)),
+        }
+    }
+
+    /// Finds the symbol without performing the derivation, by descending from the axiom into
+    /// the successor that contains the index, skipping the lengths its siblings expand to
+    fn rule_after_iterations(&self, n_iterations: u32, symbol_index: usize) -> Option<usize> {
+        // Length every symbol expands to after 0, 1, ... derivation steps
+        let mut lengths = vec![vec![1_u64; self.alphabet.len()]];
         for _ in 0..n_iterations {
-            s = self.apply_rules(&s);
+            let previous = lengths.last().unwrap();
+            let next = (0..self.alphabet.len())
+                .map(|index| match &self.production_rules[index] {
+                    Some(replacement) => replacement.iter().fold(0, |length: u64, symbol| {
+                        length.saturating_add(previous[symbol.index()])
+                    }),
+                    None => 1,
+                })
+                .collect();
+            lengths.push(next);
         }
-        s
+
+        let mut remaining = symbol_index as u64;
+        let mut rule = None;
+        let mut candidates: &[SymbolId] = &self.axiom;
+        for lengths in lengths[1..].iter().rev() {
+            let symbol = candidates.iter().copied().find(|symbol| {
+                let length = lengths[symbol.index()];
+                if remaining < length {
+                    true
+                } else {
+                    remaining -= length;
+                    false
+                }
+            })?;
+            let Some(replacement) = self.successor(symbol) else {
+                // The symbol stays as it is in all later steps
+                return rule;
+            };
+            rule = self.rule_indices[symbol.index()];
+            candidates = replacement;
+        }
+        usize::try_from(remaining)
+            .ok()
+            .and_then(|index| candidates.get(index))?;
+        rule
+    }
+
+    /// Predicts the size of every derivation step up to `n_iterations`, starting with the
+    /// axiom. Counts of each symbol are multiplied by the growth matrix of the rules (the number
+    /// of each symbol in the successor of another) once per step.
+    pub fn estimate_growth(&self, n_iterations: u32) -> Vec<GrowthEstimate> {
+        let is_segment: Vec<bool> = self
+            .alphabet
+            .names()
+            .map(TurtleInterpreter::is_segment)
+            .collect();
+        let mut counts = self.axiom_counts();
+        let mut estimates = vec![Self::estimate_from_counts(&counts, &is_segment)];
+        for _ in 0..n_iterations {
+            counts = self.next_counts(&counts);
+            estimates.push(Self::estimate_from_counts(&counts, &is_segment));
+        }
+        estimates
+    }
+
+    /// Occurrences of every symbol of the alphabet in the axiom
+    fn axiom_counts(&self) -> Vec<u64> {
+        let mut counts = vec![0_u64; self.alphabet.len()];
+        for symbol in &self.axiom {
+            counts[symbol.index()] += 1;
+        }
+        counts
     }
 
-    fn apply_rules(&self, input: &str) -> String {
-        input
-            .chars()
-            .map(|symbol| {
-                if let Some(replacement) = self.production_rules.get(&symbol) {
-                    replacement.clone()
+    /// Occurrences of every symbol after one derivation step, saturating instead of overflowing
+    fn next_counts(&self, counts: &[u64]) -> Vec<u64> {
+        let mut next_counts = vec![0_u64; self.alphabet.len()];
+        for (index, &count) in counts.iter().enumerate() {
+            if let Some(replacement) = &self.production_rules[index] {
+                for new_symbol in replacement {
+                    let entry = &mut next_counts[new_symbol.index()];
+                    *entry = entry.saturating_add(count);
+                }
+            } else {
+                next_counts[index] = next_counts[index].saturating_add(count);
+            }
+        }
+        next_counts
+    }
+
+    /// Returns every derivation step from the axiom up to `n_iterations`, each truncated to its
+    /// first `max_symbols` symbols. Rewriting is context-free, so the truncated steps are
+    /// derived from each other and exceeding the growth limits is not an error. Symbol counts
+    /// and lengths are those of the whole steps.
+    pub fn history(&self, n_iterations: u32, max_symbols: usize) -> Vec<HistoryStep> {
+        let mut symbols: Vec<SymbolId> = self.axiom.iter().copied().take(max_symbols).collect();
+        let mut step = HistoryStep {
+            rules: vec![None; symbols.len()],
+            symbols,
+            counts: self.axiom_counts(),
+            length: self.axiom.len() as u64,
+        };
+
+        let mut history = Vec::new();
+        for _ in 0..n_iterations {
+            symbols = Vec::new();
+            let mut rules = Vec::new();
+            for &symbol in &step.symbols {
+                if symbols.len() >= max_symbols {
+                    break;
+                }
+                if let Some(replacement) = self.successor(symbol) {
+                    symbols.extend_from_slice(replacement);
+                    rules.resize(symbols.len(), self.rule_indices[symbol.index()]);
                 } else {
-                    symbol.to_string()
+                    symbols.push(symbol);
+                    rules.push(None);
                 }
-            })
-            .collect()
+            }
+            symbols.truncate(max_symbols);
+            rules.truncate(max_symbols);
+
+            let counts = self.next_counts(&step.counts);
+            let length = counts
+                .iter()
+                .fold(0, |length: u64, &count| length.saturating_add(count));
+            history.push(step);
+            step = HistoryStep {
+                symbols,
+                rules,
+                counts,
+                length,
+            };
+        }
+        history.push(step);
+        history
+    }
+
+    fn estimate_from_counts(counts: &[u64], is_segment: &[bool]) -> GrowthEstimate {
+        let mut estimate = GrowthEstimate {
+            length: 0,
+            segments: 0,
+        };
+        for (&count, &is_segment) in counts.iter().zip(is_segment) {
+            estimate.length = estimate.length.saturating_add(count);
+            if is_segment {
+                estimate.segments = estimate.segments.saturating_add(count);
+            }
+        }
+        estimate
+    }
+
+    /// Returns an error if any derivation step up to `n_iterations` exceeds the limits
+    pub fn check_growth(&self, n_iterations: u32) -> Result<(), String> {
+        for (iteration, estimate) in self.estimate_growth(n_iterations).iter().enumerate() {
+            if estimate.length > self.limits.max_length {
+                return Err(format!(
+                    "Iteration {iteration} would have {} symbols, more than the limit of {}",
+                    estimate.length, self.limits.max_length
+                ));
+            }
+            if estimate.segments > self.limits.max_segments {
+                return Err(format!(
+                    "Iteration {iteration} would have {} segments, more than the limit of {}",
+                    estimate.segments, self.limits.max_segments
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Lazily yields the symbols after `n_iterations` derivation steps, expanding every symbol
+    /// depth-first as it is reached, so that only one successor per step is held at a time
+    pub fn derive(&self, n_iterations: u32) -> Result<Derivation<'_>, String> {
+        self.check_growth(n_iterations)?;
+        Ok(Derivation {
+            production_rules: &self.production_rules,
+            stack: vec![(self.axiom.iter(), n_iterations)],
+        })
+    }
+
+    /// Like `derive`, but collected. Rewriting is context-free, so every derivation step is
+    /// split into chunks that are rewritten on the rayon thread pool and concatenated.
+    /// Returns `None` once `is_cancelled` does, which is checked before every chunk.
+    pub fn derive_parallel(
+        &self,
+        n_iterations: u32,
+        is_cancelled: impl Fn() -> bool + Sync,
+    ) -> Result<Option<Vec<SymbolId>>, String> {
+        self.check_growth(n_iterations)?;
+
+        let mut derivation = self.axiom.clone();
+        for _ in 0..n_iterations {
+            let chunks = derivation
+                .par_chunks(PARALLEL_CHUNK_LENGTH)
+                .map(|chunk| (!is_cancelled()).then(|| self.rewrite(chunk)))
+                .collect::<Option<Vec<Vec<SymbolId>>>>();
+            let Some(chunks) = chunks else {
+                return Ok(None);
+            };
+            derivation = chunks.concat();
+        }
+        Ok(Some(derivation))
+    }
+
+    /// Performs a single derivation step
+    fn rewrite(&self, input: &[SymbolId]) -> Vec<SymbolId> {
+        let mut output = Vec::with_capacity(input.len());
+        for &symbol in input {
+            match self.successor(symbol) {
+                Some(replacement) => output.extend_from_slice(replacement),
+                None => output.push(symbol),
+            }
+        }
+        output
+    }
+
+    /// Like `derive`, but collected, and also returns the iteration in which each symbol of the
+    /// result was created. When a symbol is rewritten, the first occurrence of the same symbol in
+    /// its replacement is treated as its continuation and keeps the original birth iteration.
+    pub fn generate_with_births(
+        &self,
+        n_iterations: u32,
+    ) -> Result<(Vec<SymbolId>, Vec<u32>), String> {
+        self.check_growth(n_iterations)?;
+
+        let mut s = self.axiom.clone();
+        let mut births = vec![0; s.len()];
+        for iteration in 1..=n_iterations {
+            (s, births) = self.apply_rules_with_births(&s, &births, iteration);
+        }
+        Ok((s, births))
+    }
+
+    fn apply_rules_with_births(
+        &self,
+        input: &[SymbolId],
+        births: &[u32],
+        iteration: u32,
+    ) -> (Vec<SymbolId>, Vec<u32>) {
+        let mut output = Vec::with_capacity(input.len());
+        let mut output_births = Vec::with_capacity(births.len());
+
+        for (&symbol, &birth) in input.iter().zip(births) {
+            let Some(replacement) = self.successor(symbol) else {
+                output.push(symbol);
+                output_births.push(birth);
+                continue;
+            };
+
+            let mut continued = false;
+            for &new_symbol in replacement {
+                output.push(new_symbol);
+                if !continued && new_symbol == symbol {
+                    continued = true;
+                    output_births.push(birth);
+                } else {
+                    output_births.push(iteration);
+                }
+            }
+        }
+
+        (output, output_births)
+    }
+}
+
+/// Symbols of a derivation, produced on demand by `LSystem::derive`
+pub struct Derivation<'a> {
+    production_rules: &'a [Option<Vec<SymbolId>>],
+    /// Successors being expanded, with the number of derivation steps left for their symbols
+    stack: Vec<(std::slice::Iter<'a, SymbolId>, u32)>,
+}
+
+impl Iterator for Derivation<'_> {
+    type Item = SymbolId;
+
+    fn next(&mut self) -> Option<SymbolId> {
+        loop {
+            let (symbols, steps_left) = self.stack.last_mut()?;
+            let steps_left = *steps_left;
+            let Some(&symbol) = symbols.next() else {
+                self.stack.pop();
+                continue;
+            };
+
+            match &self.production_rules[symbol.index()] {
+                Some(replacement) if steps_left > 0 => {
+                    self.stack.push((replacement.iter(), steps_left - 1));
+                }
+                _ => return Some(symbol),
+            }
+        }
     }
 }
 
@@ -39,20 +596,307 @@ impl LSystem {
 mod tests {
     use super::*;
 
+    fn to_text(lsystem: &LSystem, symbols: impl IntoIterator<Item = SymbolId>) -> String {
+        let names: Vec<&str> = lsystem.alphabet().names().collect();
+        symbols
+            .into_iter()
+            .map(|symbol| symbol::written(names[symbol.index()]))
+            .collect()
+    }
+
+    fn derive_text(lsystem: &LSystem, n_iterations: u32) -> String {
+        to_text(lsystem, lsystem.derive(n_iterations).unwrap())
+    }
+
     #[test]
     fn test_generation() {
         // From wikipedia https://en.wikipedia.org/wiki/L-system
-        let axiom = "A";
-        let mut production_rules = HashMap::new();
-        production_rules.insert('A', "AB".to_string());
-        production_rules.insert('B', "A".to_string());
-
-        let lsystem = LSystem::new(axiom, production_rules);
-
-        assert_eq!(lsystem.generate(0), "A");
-        assert_eq!(lsystem.generate(1), "AB");
-        assert_eq!(lsystem.generate(2), "ABA");
-        assert_eq!(lsystem.generate(3), "ABAAB");
-        assert_eq!(lsystem.generate(4), "ABAABABA");
+        let lsystem = LSystem::new("A", [("A", "AB"), ("B", "A")]);
+
+        assert_eq!(derive_text(&lsystem, 0), "A");
+        assert_eq!(derive_text(&lsystem, 1), "AB");
+        assert_eq!(derive_text(&lsystem, 2), "ABA");
+        assert_eq!(derive_text(&lsystem, 3), "ABAAB");
+        assert_eq!(derive_text(&lsystem, 4), "ABAABABA");
+    }
+
+    #[test]
+    fn test_multi_character_symbols() {
+        let lsystem = LSystem::new(
+            "{Apex}",
+            [("{Apex}", "F[+{Leaf}]{Apex}"), ("{Leaf}", "{Leaf}")],
+        );
+
+        assert_eq!(lsystem.alphabet().len(), 6);
+        assert_eq!(derive_text(&lsystem, 2), "F[+{Leaf}]F[+{Leaf}]{Apex}");
+        assert_eq!(lsystem.derive(2).unwrap().count(), 11);
+        assert_eq!(
+            lsystem.estimate_growth(2)[2],
+            GrowthEstimate {
+                length: 11,
+                segments: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_generation_with_births() {
+        let lsystem = LSystem::new("A", [("A", "AB"), ("B", "A")]);
+
+        let with_births = |n| {
+            let (symbols, births) = lsystem.generate_with_births(n).unwrap();
+            (to_text(&lsystem, symbols), births)
+        };
+        assert_eq!(with_births(0), ("A".to_string(), vec![0]));
+        assert_eq!(with_births(1), ("AB".to_string(), vec![0, 1]));
+        assert_eq!(with_births(3), ("ABAAB".to_string(), vec![0, 3, 3, 2, 3]));
+        assert_eq!(with_births(4).0, derive_text(&lsystem, 4));
+    }
+
+    #[test]
+    fn test_history() {
+        let lsystem = LSystem::new("A", [("A", "AB"), ("B", "A")]);
+        let history = lsystem.history(4, 5);
+
+        assert_eq!(history.len(), 5);
+        for (n, step) in (0..).zip(&history) {
+            let derivation = derive_text(&lsystem, n);
+            assert_eq!(step.length, derivation.len() as u64);
+            assert_eq!(
+                to_text(&lsystem, step.symbols.iter().copied()),
+                derivation.chars().take(5).collect::<String>()
+            );
+        }
+
+        let b = lsystem.alphabet().get("B").unwrap();
+        assert_eq!(history[4].counts[b.index()], 3);
+        assert_eq!(history[0].rules, [None]);
+        assert_eq!(
+            history[3].rules,
+            [Some(0), Some(0), Some(1), Some(0), Some(0)]
+        );
+
+        let lsystem = LSystem::new("XF", [("X", "X+"), ("F", "F")]);
+        assert_eq!(lsystem.history(1, 10)[1].rules, [Some(0), Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn test_rule_of_symbol() {
+        let lsystem = LSystem::new("A", [("A", "AB"), ("B", "A"), ("C", "C")]);
+        // ABAAB after 3 steps, every symbol created by rule 0 or rule 1
+        let rules: Vec<_> = (0..6)
+            .map(|index| {
+                lsystem
+                    .rule_of_symbol(GrowthStage::Iterations(3), index)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(rules, [Some(0), Some(0), Some(1), Some(0), Some(0), None]);
+        assert_eq!(
+            lsystem.rule_of_symbol(GrowthStage::Iterations(0), 0),
+            Ok(None)
+        );
+
+        // Timed derivations agree with the discrete ones
+        for (index, rule) in rules.iter().enumerate() {
+            assert_eq!(
+                lsystem.rule_of_symbol(GrowthStage::Time(3.0), index),
+                Ok(*rule)
+            );
+        }
+
+        // Symbols without a production keep the rule that created them
+        let lsystem = LSystem::new("X", [("X", "F[X]"), ("F", "FF")]);
+        let rules: Vec<_> = (0..8)
+            .map(|index| {
+                lsystem
+                    .rule_of_symbol(GrowthStage::Iterations(2), index)
+                    .unwrap()
+            })
+            .collect();
+        // FF[F[X]]
+        assert_eq!(
+            rules,
+            [
+                Some(1),
+                Some(1),
+                Some(0),
+                Some(0),
+                Some(0),
+                Some(0),
+                Some(0),
+                Some(0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_derive_at_matches_generation() {
+        let lsystem = LSystem::new("A", [("A", "AB"), ("B", "A")]);
+
+        for n in 0..5_u8 {
+            let modules = lsystem.derive_at(f32::from(n)).unwrap();
+            assert_eq!(
+                to_text(&lsystem, modules.iter().map(|module| module.symbol)),
+                derive_text(&lsystem, u32::from(n))
+            );
+        }
+    }
+
+    #[test]
+    fn test_derive_at_growth() {
+        let lsystem = LSystem::new("F", [("F", "FF")]).with_terminal_ages([("F", 2.0)]);
+
+        let modules = lsystem.derive_at(1.0).unwrap();
+        assert_eq!(modules.len(), 1);
+        assert!((modules[0].age - 1.0).abs() < f32::EPSILON);
+
+        let modules = lsystem.derive_at(3.0).unwrap();
+        assert_eq!(modules.len(), 2);
+        assert!(modules[0].mature);
+        assert!(!modules[1].mature);
+        assert!((modules[0].length(GrowthFunction::Linear) - 1.0).abs() < f32::EPSILON);
+        assert!((modules[1].length(GrowthFunction::Linear) - 0.5).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_derive_at_grows_segments_without_production() {
+        let lsystem = LSystem::new("X", [("X", "F[+X]F[-X]+X")]);
+        let lengths: Vec<f32> = lsystem
+            .derive_at(2.5)
+            .unwrap()
+            .iter()
+            .filter(|module| lsystem.alphabet().name(module.symbol) == "F")
+            .map(|module| module.length(GrowthFunction::Linear))
+            .collect();
+
+        // The segments of the first step are grown, the newest ones half way
+        assert!((lengths[0] - 1.0).abs() < f32::EPSILON);
+        let newest = lengths.iter().copied().fold(f32::INFINITY, f32::min);
+        assert!((newest - 0.5).abs() < f32::EPSILON);
+        assert!((lsystem.min_terminal_age() - DEFAULT_TERMINAL_AGE).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_growth_estimate_matches_generation() {
+        let lsystem = LSystem::new("FX", [("F", "F[+F]X"), ("X", "FX")]);
+
+        for (n, estimate) in (0..5).zip(lsystem.estimate_growth(4)) {
+            let derivation = derive_text(&lsystem, n);
+            assert_eq!(estimate.length, derivation.chars().count() as u64);
+            assert_eq!(
+                estimate.segments,
+                derivation.chars().filter(|&c| c == 'F').count() as u64
+            );
+        }
+    }
+
+    #[test]
+    fn test_growth_limits() {
+        let lsystem = LSystem::new("F", [("F", "FFFFF")]).with_limits(GrowthLimits {
+            max_length: 1000,
+            max_segments: 100,
+        });
+
+        assert!(lsystem.derive(2).is_ok());
+        let error = lsystem.derive(3).err().unwrap();
+        assert!(error.starts_with("Iteration 3 would have 125 segments"));
+        assert!(lsystem.generate_with_births(3).is_err());
+        assert!(lsystem.derive_at(2.5).is_ok());
+        let error = lsystem.derive_at(3.0).err().unwrap();
+        assert_eq!(
+            error,
+            "At time 3.00 the plant would have more than the limit of 100 segments"
+        );
+    }
+
+    #[test]
+    fn test_timed_growth_limits_use_actual_derivation() {
+        // X fires often but never grows, so only the three doublings of F count
+        let lsystem = LSystem::new("FX", [("F", "FF"), ("X", "X")])
+            .with_terminal_ages([("X", 0.1)])
+            .with_limits(GrowthLimits {
+                max_length: 10,
+                max_segments: 8,
+            });
+
+        assert_eq!(lsystem.derive_at(3.5).unwrap().len(), 9);
+        assert!(lsystem.derive_at(4.0).is_err());
+    }
+
+    #[test]
+    fn test_derivation_stream() {
+        let lsystem = LSystem::new("XF-F", [("F", "F[+F]F"), ("X", "")]);
+
+        let derivation = derive_text(&lsystem, 2);
+        assert_eq!(derivation, "F[+F]F[+F[+F]F]F[+F]F-F[+F]F[+F[+F]F]F[+F]F");
+        assert_eq!(
+            to_text(&lsystem, lsystem.generate_with_births(2).unwrap().0),
+            derivation
+        );
+
+        let mut symbols = lsystem.derive(2).unwrap();
+        assert_eq!(symbols.next(), lsystem.alphabet().get("F"));
+        assert!(symbols.stack.len() <= 3);
+    }
+
+    fn trunk_tree() -> LSystem {
+        LSystem::new(
+            "FX",
+            [("X", "[+FX][-FX][&FX][^FX][\\FX][/FX]"), ("F", "FF")],
+        )
+        .with_limits(GrowthLimits {
+            max_length: u64::MAX,
+            max_segments: u64::MAX,
+        })
+    }
+
+    #[test]
+    fn test_parallel_derivation() {
+        let lsystem = trunk_tree();
+        for n in 0..5 {
+            assert_eq!(
+                lsystem.derive_parallel(n, || false).unwrap(),
+                Some(lsystem.derive(n).unwrap().collect::<Vec<SymbolId>>())
+            );
+        }
+        assert_eq!(lsystem.derive_parallel(2, || true), Ok(None));
+    }
+
+    /// Compares streaming, single threaded and parallel derivation, run with `just bench`
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_parallel_derivation() {
+        use std::time::Instant;
+
+        let lsystem = trunk_tree();
+        let single_thread = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        println!("{} threads", rayon::current_num_threads());
+
+        for n in 5..=8 {
+            let start = Instant::now();
+            let streamed = lsystem.derive(n).unwrap().count();
+            let streaming_time = start.elapsed();
+
+            let start = Instant::now();
+            let sequential =
+                single_thread.install(|| lsystem.derive_parallel(n, || false).unwrap().unwrap());
+            let sequential_time = start.elapsed();
+
+            let start = Instant::now();
+            let parallel = lsystem.derive_parallel(n, || false).unwrap().unwrap();
+            let parallel_time = start.elapsed();
+
+            assert_eq!(sequential, parallel);
+            assert_eq!(streamed, parallel.len());
+            println!(
+                "{n} iterations, {streamed} symbols: streaming {streaming_time:?}, \
+                 1 thread {sequential_time:?}, parallel {parallel_time:?} ({:.2}x)",
+                sequential_time.as_secs_f64() / parallel_time.as_secs_f64()
+            );
+        }
     }
 }
@@ -2,16 +2,31 @@ use app::App;
 use winit::event_loop::EventLoop;
 extern crate nalgebra_glm as glm;
 
+mod animation;
 mod app;
 mod camera;
 mod common;
+mod culling;
+mod export;
+mod generation;
 mod gui;
+mod layout;
+mod lod;
 mod lsystem;
 mod model_loader;
+mod picking;
+mod placement;
 mod renderer;
 mod scene;
+mod scene_file;
+mod screenshot;
 mod shaders;
+mod stats;
+mod symbol;
+mod terrain;
 mod turtle;
+mod undo;
+mod validation;
 
 fn main() {
     env_logger::init();
@@ -1,10 +1,92 @@
 use crate::common::ModelSelection;
-use tobj::{Material, Model};
+use image::RgbaImage;
+use std::path::Path;
+use std::sync::Arc;
+use tobj::{Material, Mesh, Model};
 
 #[derive(Clone, Debug)]
 pub struct Model3D {
     pub geometry: Model,
     pub material: Material,
+    /// Texture replacing the diffuse color, from `map_Kd` of the material
+    pub diffuse_texture: Option<TextureImage>,
+    /// Tangent-space normal map, from `map_Bump` or `bump` of the material
+    pub normal_texture: Option<TextureImage>,
+    /// Tangent of every vertex of the mesh, see `tangents`
+    pub tangents: Vec<[f32; 3]>,
+    /// Thin surface lit from both sides, whose texture is mapped once onto every segment
+    pub foliage: bool,
+}
+
+/// Image of a texture with the path it was loaded from, which identifies it on the GPU
+#[derive(Clone, Debug)]
+pub struct TextureImage {
+    pub path: String,
+    pub image: Arc<RgbaImage>,
+}
+
+impl Model3D {
+    pub fn triangle_count(&self) -> usize {
+        self.geometry.mesh.indices.len() / 3
+    }
+}
+
+/// Computes the direction of increasing U texture coordinate at every vertex, which together
+/// with the normal spans the tangent space of normal maps. Vertices of meshes without texture
+/// coordinates get zero tangents.
+pub fn tangents(mesh: &Mesh) -> Vec<[f32; 3]> {
+    let n_vertices = mesh.positions.len() / 3;
+    let mut tangents = vec![glm::Vec3::zeros(); n_vertices];
+    if mesh.texcoords.len() != n_vertices * 2 {
+        return vec![[0.0; 3]; n_vertices];
+    }
+
+    let position = |i: usize| glm::make_vec3(&mesh.positions[i * 3..i * 3 + 3]);
+    let texcoord = |i: usize| glm::make_vec2(&mesh.texcoords[i * 2..i * 2 + 2]);
+    for triangle in mesh.indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|corner| triangle[corner] as usize);
+        let (edge1, edge2) = (position(b) - position(a), position(c) - position(a));
+        let (delta1, delta2) = (texcoord(b) - texcoord(a), texcoord(c) - texcoord(a));
+        let determinant = delta1.x * delta2.y - delta2.x * delta1.y;
+        if determinant.abs() < f32::EPSILON {
+            continue;
+        }
+        // Triangles add up weighted by the area of their texture mapping
+        let tangent = (edge1 * delta2.y - edge2 * delta1.y) * determinant.signum();
+        for vertex in [a, b, c] {
+            tangents[vertex] += tangent;
+        }
+    }
+
+    tangents
+        .into_iter()
+        .map(|tangent| {
+            if tangent.norm() < f32::EPSILON {
+                [0.0; 3]
+            } else {
+                tangent.normalize().into()
+            }
+        })
+        .collect()
+}
+
+/// Loads a texture named by the material from the directory of the model. Textures are optional,
+/// so one that fails to load is left out and the color of the material is used instead.
+fn load_texture(model_path: &str, name: Option<&str>) -> Option<TextureImage> {
+    let path = Path::new(model_path)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(name?);
+    match image::open(&path) {
+        Ok(image) => Some(TextureImage {
+            path: path.display().to_string(),
+            image: Arc::new(image.into_rgba8()),
+        }),
+        Err(e) => {
+            log::warn!("Failed to load texture {}: {e}", path.display());
+            None
+        }
+    }
 }
 
 /// Load a 3D model from .obj and .mtl files containing one model and one material each.
@@ -28,8 +110,18 @@ fn load_obj_file(path: &str) -> Model3D {
 
     let geometry = models.first().expect("Expected model").clone();
     let material = materials.first().expect("Expected material").clone();
+    let diffuse_texture = load_texture(path, material.diffuse_texture.as_deref());
+    let normal_texture = load_texture(path, material.normal_texture.as_deref());
+    let tangents = tangents(&geometry.mesh);
 
-    Model3D { geometry, material }
+    Model3D {
+        geometry,
+        material,
+        diffuse_texture,
+        normal_texture,
+        tangents,
+        foliage: false,
+    }
 }
 
 pub fn load_floor() -> Model3D {
@@ -52,18 +144,25 @@ fn load_leaf() -> Model3D {
     load_obj_file("assets/models/leaf.obj")
 }
 
+fn load_leaf_card() -> Model3D {
+    load_obj_file("assets/models/leaf_card.obj")
+}
+
 fn load_twig() -> Model3D {
     load_obj_file("assets/models/twig.obj")
 }
 
 pub fn load_model(selected_model: ModelSelection) -> Model3D {
-    match selected_model {
+    let mut model = match selected_model {
         ModelSelection::Cylinder => load_cylinder(),
         ModelSelection::Branch => load_branch(),
         ModelSelection::Leaf => load_leaf(),
+        ModelSelection::LeafCard => load_leaf_card(),
         ModelSelection::Twig => load_twig(),
         ModelSelection::Monkey => load_monkey(),
-    }
+    };
+    model.foliage = selected_model.is_foliage();
+    model
 }
 
 #[cfg(test)]
@@ -122,6 +221,15 @@ mod tests {
         let model = load_branch();
         check_if_model_loaded_correctly(&model);
         display_model_info(&model);
+
+        // The branch is covered in bark, whose normal map is oriented by the tangents
+        assert!(model.diffuse_texture.is_some());
+        assert!(model.normal_texture.is_some());
+        assert_eq!(
+            model.geometry.mesh.texcoords.len() / 2,
+            model.tangents.len()
+        );
+        assert!(model.tangents.iter().any(|tangent| tangent.contains(&1.0)));
     }
 
     #[test]
@@ -137,4 +245,60 @@ mod tests {
         check_if_model_loaded_correctly(&model);
         display_model_info(&model);
     }
+
+    #[test]
+    fn leaf_card_loads_correctly() {
+        let model = load_model(ModelSelection::LeafCard);
+        check_if_model_loaded_correctly(&model);
+        display_model_info(&model);
+        assert!(model.foliage);
+        assert_eq!(model.triangle_count(), 2);
+
+        // The leaf is cut out of the card by the alpha of its texture
+        let texture = model.diffuse_texture.expect("Expected leaf texture");
+        let alphas: Vec<u8> = texture.image.pixels().map(|pixel| pixel.0[3]).collect();
+        assert!(alphas.contains(&0));
+        assert!(alphas.contains(&255));
+    }
+
+    #[test]
+    fn textured_model_loads_correctly() {
+        let directory = std::env::temp_dir().join("lsystemtreegen_textured_model");
+        std::fs::create_dir_all(&directory).unwrap();
+        RgbaImage::from_pixel(2, 2, image::Rgba([90, 60, 30, 255]))
+            .save(directory.join("bark.png"))
+            .unwrap();
+        std::fs::write(
+            directory.join("quad.mtl"),
+            "newmtl Bark\nKa 0.1 0.1 0.1\nKd 1 1 1\nKs 0 0 0\nmap_Kd bark.png\nmap_Bump missing.png\n",
+        )
+        .unwrap();
+        std::fs::write(
+            directory.join("quad.obj"),
+            "mtllib quad.mtl\no Quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nvn 0 0 1\nusemtl Bark\n\
+             f 1/1/1 2/2/1 3/3/1 4/4/1\n",
+        )
+        .unwrap();
+
+        let model = load_obj_file(directory.join("quad.obj").to_str().unwrap());
+        check_if_model_loaded_correctly(&model);
+        assert_eq!(model.geometry.mesh.texcoords.len(), 8);
+        let texture = model.diffuse_texture.expect("Expected diffuse texture");
+        assert_eq!(texture.image.get_pixel(1, 1).0, [90, 60, 30, 255]);
+        // A missing normal map falls back to the normals of the mesh
+        assert!(model.normal_texture.is_none());
+
+        // U grows along X, so the tangent points along X at every vertex
+        for &tangent in &model.tangents {
+            assert!(glm::distance(&glm::Vec3::from(tangent), &glm::vec3(1.0, 0.0, 0.0)) < 1e-5);
+        }
+    }
+
+    #[test]
+    fn untextured_model_has_zero_tangents() {
+        let model = load_twig();
+        assert!(model.diffuse_texture.is_none());
+        assert!(model.tangents.iter().flatten().all(|&c| c == 0.0));
+    }
 }
@@ -0,0 +1,341 @@
+use crate::culling::Aabb;
+use crate::scene::{Scene, Species};
+use crate::terrain::Terrain;
+use crate::turtle::Segment;
+use glm::{Mat4, Vec3};
+use tobj::Model;
+
+/// Half-line in world space
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+/// Step of the search for the first terrain hit, small enough not to skip over hills
+const TERRAIN_RAY_STEP: f32 = 0.25;
+/// Distance beyond which the terrain is not searched for hits
+const TERRAIN_RAY_MAX_DISTANCE: f32 = 200.0;
+
+/// Tree segment hit by a ray
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selection {
+    pub tree_index: usize,
+    /// Level of detail the segment index refers to
+    pub level: usize,
+    pub segment_index: usize,
+}
+
+/// Details of the selected tree and segment shown in the inspector
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    pub tree_index: usize,
+    pub species_name: String,
+    pub displacement_matrix: Mat4,
+    pub segment_index: usize,
+    pub segment: Segment,
+    /// Name of the symbol that drew the segment
+    pub symbol_name: String,
+    /// Rule that created the symbol, `None` for symbols of the axiom
+    pub rule: Result<Option<String>, String>,
+}
+
+/// Cylinder around a base model, standing on the origin along the Y axis
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingCylinder {
+    radius: f32,
+    min_y: f32,
+    max_y: f32,
+}
+
+impl BoundingCylinder {
+    pub fn from_model(model: &Model) -> Self {
+        let bounds = Aabb::from_model(model);
+        if bounds.is_empty() {
+            return Self {
+                radius: 0.0,
+                min_y: 0.0,
+                max_y: 0.0,
+            };
+        }
+        let radius = model
+            .mesh
+            .positions
+            .chunks(3)
+            .map(|v| v[0].hypot(v[2]))
+            .fold(0.0, f32::max);
+        Self {
+            radius,
+            min_y: bounds.min.y,
+            max_y: bounds.max.y,
+        }
+    }
+}
+
+impl Ray {
+    /// Ray from the camera through a point in normalized device coordinates (-1..1, Y up)
+    pub fn from_screen(ndc: (f32, f32), view_projection: &Mat4, camera_position: &Vec3) -> Self {
+        let inverse = glm::inverse(view_projection);
+        let point = inverse * glm::vec4(ndc.0, ndc.1, 0.5, 1.0);
+        let point = point.xyz() / point.w;
+        Self {
+            origin: *camera_position,
+            direction: glm::normalize(&(point - camera_position)),
+        }
+    }
+
+    /// Ray in the space that `matrix` transforms into world space.
+    /// Distances along the ray are preserved, as the direction is not normalized.
+    fn to_local(self, matrix: &Mat4) -> Self {
+        let inverse = glm::inverse(matrix);
+        Self {
+            origin: (inverse * glm::vec4(self.origin.x, self.origin.y, self.origin.z, 1.0)).xyz(),
+            direction: (inverse
+                * glm::vec4(self.direction.x, self.direction.y, self.direction.z, 0.0))
+            .xyz(),
+        }
+    }
+
+    pub fn point_at(&self, distance: f32) -> Vec3 {
+        self.origin + self.direction * distance
+    }
+
+    /// Returns the first point where the ray hits the terrain surface from above
+    pub fn intersect_terrain(&self, terrain: &Terrain) -> Option<Vec3> {
+        let height_above = |distance: f32| {
+            let point = self.point_at(distance);
+            point.y - terrain.height_at(point.x, point.z)
+        };
+        if height_above(0.0) < 0.0 {
+            return None;
+        }
+
+        // March along the ray until it gets below the surface, then refine by bisection
+        let mut near = 0.0;
+        let mut far = TERRAIN_RAY_STEP;
+        while height_above(far) >= 0.0 {
+            near = far;
+            far += TERRAIN_RAY_STEP;
+            if far > TERRAIN_RAY_MAX_DISTANCE {
+                return None;
+            }
+        }
+        for _ in 0..16 {
+            let middle = 0.5 * (near + far);
+            if height_above(middle) >= 0.0 {
+                near = middle;
+            } else {
+                far = middle;
+            }
+        }
+        Some(self.point_at(far))
+    }
+
+    /// Distance to the closest point of the box along the ray (slab method),
+    /// zero if the origin is inside
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        if aabb.is_empty() {
+            return None;
+        }
+
+        let mut t_min = 0.0_f32;
+        let mut t_max = f32::INFINITY;
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / self.direction[axis];
+            let t0 = (aabb.min[axis] - self.origin[axis]) * inverse_direction;
+            let t1 = (aabb.max[axis] - self.origin[axis]) * inverse_direction;
+            t_min = t_min.max(t0.min(t1));
+            t_max = t_max.min(t0.max(t1));
+        }
+        (t_min <= t_max).then_some(t_min)
+    }
+
+    /// Distance to the first hit of the closed cylinder along the ray
+    #[allow(clippy::many_single_char_names)]
+    pub fn intersect_cylinder(&self, cylinder: &BoundingCylinder) -> Option<f32> {
+        let (o, d) = (self.origin, self.direction);
+        let mut hits = Vec::with_capacity(4);
+
+        // Side: x^2 + z^2 = r^2
+        let a = d.x * d.x + d.z * d.z;
+        let b = 2.0 * (o.x * d.x + o.z * d.z);
+        let c = o.x * o.x + o.z * o.z - cylinder.radius * cylinder.radius;
+        let discriminant = b * b - 4.0 * a * c;
+        if a > f32::EPSILON && discriminant >= 0.0 {
+            let root = discriminant.sqrt();
+            for t in [(-b - root) / (2.0 * a), (-b + root) / (2.0 * a)] {
+                let y = o.y + t * d.y;
+                if (cylinder.min_y..=cylinder.max_y).contains(&y) {
+                    hits.push(t);
+                }
+            }
+        }
+
+        // Caps
+        if d.y.abs() > f32::EPSILON {
+            for cap_y in [cylinder.min_y, cylinder.max_y] {
+                let t = (cap_y - o.y) / d.y;
+                let (x, z) = (o.x + t * d.x, o.z + t * d.z);
+                if x * x + z * z <= cylinder.radius * cylinder.radius {
+                    hits.push(t);
+                }
+            }
+        }
+
+        hits.into_iter()
+            .filter(|&t| t >= 0.0)
+            .min_by(f32::total_cmp)
+    }
+}
+
+/// Returns the segment closest to the ray origin among all trees, tested at their current
+/// level of detail. Trees of the species at the index of `species_override` are tested with
+/// the given species instead, as drawn in variant B of the split view.
+pub fn pick_segment(
+    scene: &Scene,
+    ray: &Ray,
+    species_override: Option<(usize, &Species)>,
+) -> Option<Selection> {
+    let mut closest: Option<(f32, Selection)> = None;
+
+    for (tree_index, (bounding_box, &species_index)) in scene
+        .bounding_boxes()
+        .iter()
+        .zip(scene.tree_species_indices())
+        .enumerate()
+    {
+        let (species, bounding_box) = match species_override {
+            Some((index, species)) if index == species_index => (
+                species,
+                species
+                    .local_bounds()
+                    .transformed(&scene.displacement_matrices[tree_index]),
+            ),
+            _ => match scene.tree_species(tree_index) {
+                Some(species) => (species, *bounding_box),
+                None => continue,
+            },
+        };
+        let Some(box_distance) = ray.intersect_aabb(&bounding_box) else {
+            continue;
+        };
+        if closest.is_some_and(|(distance, _)| distance < box_distance) {
+            continue;
+        }
+
+        let cylinder = species.bounding_cylinder();
+        let (level, tree_matrix) = scene.tree_drawing(tree_index, species);
+        for (segment_index, segment) in species.segments(level).iter().enumerate() {
+            let local_ray = ray.to_local(&(tree_matrix * segment.transformation));
+            let Some(distance) = local_ray.intersect_cylinder(cylinder) else {
+                continue;
+            };
+            if closest.is_none_or(|(closest_distance, _)| distance < closest_distance) {
+                closest = Some((
+                    distance,
+                    Selection {
+                        tree_index,
+                        level,
+                        segment_index,
+                    },
+                ));
+            }
+        }
+    }
+
+    closest.map(|(_, selection)| selection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_cylinder() -> BoundingCylinder {
+        BoundingCylinder {
+            radius: 0.5,
+            min_y: 0.0,
+            max_y: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_ray_from_screen_center() {
+        let camera_position = glm::vec3(0.0, 1.0, 5.0);
+        let view = glm::look_at(
+            &camera_position,
+            &glm::vec3(0.0, 1.0, 0.0),
+            &glm::vec3(0.0, 1.0, 0.0),
+        );
+        let projection = glm::perspective_rh_zo(1.0, 45_f32.to_radians(), 0.1, 100.0);
+        let ray = Ray::from_screen((0.0, 0.0), &(projection * view), &camera_position);
+        assert!((ray.direction - glm::vec3(0.0, 0.0, -1.0)).norm() < 1e-4);
+    }
+
+    #[test]
+    fn test_ray_aabb_intersection() {
+        let aabb = Aabb {
+            min: glm::vec3(-1.0, -1.0, -1.0),
+            max: glm::vec3(1.0, 1.0, 1.0),
+        };
+        let ray = Ray {
+            origin: glm::vec3(0.0, 0.0, 5.0),
+            direction: glm::vec3(0.0, 0.0, -1.0),
+        };
+        assert_eq!(ray.intersect_aabb(&aabb), Some(4.0));
+
+        let away = Ray {
+            direction: glm::vec3(0.0, 0.0, 1.0),
+            ..ray
+        };
+        assert_eq!(away.intersect_aabb(&aabb), None);
+    }
+
+    #[test]
+    fn test_ray_cylinder_intersection() {
+        let side = Ray {
+            origin: glm::vec3(-5.0, 0.5, 0.0),
+            direction: glm::vec3(1.0, 0.0, 0.0),
+        };
+        assert_eq!(side.intersect_cylinder(&unit_cylinder()), Some(4.5));
+
+        let top = Ray {
+            origin: glm::vec3(0.2, 3.0, 0.0),
+            direction: glm::vec3(0.0, -1.0, 0.0),
+        };
+        assert_eq!(top.intersect_cylinder(&unit_cylinder()), Some(2.0));
+
+        let above = Ray {
+            origin: glm::vec3(-5.0, 1.5, 0.0),
+            ..side
+        };
+        assert_eq!(above.intersect_cylinder(&unit_cylinder()), None);
+    }
+
+    #[test]
+    fn test_local_ray_keeps_distances() {
+        let matrix =
+            glm::translation(&glm::vec3(10.0, 0.0, 0.0)) * glm::scaling(&glm::vec3(1.0, 4.0, 1.0));
+        let ray = Ray {
+            origin: glm::vec3(10.0, 2.0, 5.0),
+            direction: glm::vec3(0.0, 0.0, -1.0),
+        };
+        let distance = ray.to_local(&matrix).intersect_cylinder(&unit_cylinder());
+        assert_eq!(distance, Some(4.5));
+    }
+
+    #[test]
+    fn test_ray_terrain_intersection() {
+        let ray = Ray {
+            origin: glm::vec3(1.0, 5.0, 2.0),
+            direction: glm::normalize(&glm::vec3(1.0, -1.0, 0.0)),
+        };
+        let hit = ray.intersect_terrain(&Terrain::flat(50.0)).unwrap();
+        assert!((hit - glm::vec3(6.0, 0.0, 2.0)).norm() < 1e-3);
+
+        let upwards = Ray {
+            direction: glm::vec3(0.0, 1.0, 0.0),
+            ..ray
+        };
+        assert!(upwards.intersect_terrain(&Terrain::flat(50.0)).is_none());
+    }
+}
@@ -0,0 +1,392 @@
+use glm::{Mat4, Vec2};
+use rand::Rng;
+use rand::distr::Distribution;
+use rand::distr::weighted::WeightedIndex;
+use rand::seq::SliceRandom;
+
+/// Number of candidates tried around every active sample in Poisson-disk sampling
+const POISSON_DISK_CANDIDATES: usize = 30;
+/// Number of rejected samples per tree after which density map sampling gives up
+const DENSITY_MAP_MAX_ATTEMPTS: usize = 1000;
+/// Largest number of derivation steps a tree may lack compared to its species
+pub const MAX_ITERATION_JITTER: u32 = 2;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum PlacementStrategy {
+    #[default]
+    Uniform,
+    PoissonDisk,
+    JitteredGrid,
+    DensityMap,
+}
+
+/// Ranges of the random variation between trees of the same species
+#[derive(Debug, Clone, PartialEq)]
+pub struct InstanceVariation {
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// Maximum angle between the trunk and the vertical axis, in degrees
+    pub max_tilt: f32,
+    /// Maximum number of derivation steps a tree may lack compared to its species
+    pub iteration_jitter: u32,
+}
+
+impl Default for InstanceVariation {
+    fn default() -> Self {
+        Self {
+            min_scale: 1.0,
+            max_scale: 1.0,
+            max_tilt: 0.0,
+            iteration_jitter: 0,
+        }
+    }
+}
+
+impl InstanceVariation {
+    /// Returns a random local transformation of a tree (rotation around its trunk, tilt and
+    /// scale) and the number of derivation steps it lacks
+    pub fn sample(&self, rng: &mut impl Rng) -> (Mat4, u32) {
+        // Every value is drawn even for empty ranges, so that the sequence of random numbers
+        // and with it the rest of the layout does not depend on the ranges
+        let y_rotation = rng.random_range(0.0..360.0_f32).to_radians();
+        let scale = rng.random_range(self.min_scale..=self.max_scale.max(self.min_scale));
+        let tilt_direction = rng.random_range(0.0..360.0_f32).to_radians();
+        let tilt = rng.random_range(0.0..=self.max_tilt.max(0.0)).to_radians();
+        let iteration_offset = rng.random_range(0..=self.iteration_jitter);
+
+        let tilt_axis = glm::vec3(tilt_direction.cos(), 0.0, tilt_direction.sin());
+        let transformation = glm::rotation(tilt, &tilt_axis)
+            * glm::rotation(y_rotation, &glm::vec3(0.0, 1.0, 0.0))
+            * glm::scaling(&glm::vec3(scale, scale, scale));
+
+        (transformation, iteration_offset)
+    }
+}
+
+/// Rectangle on the XZ plane in which trees are placed
+#[derive(Debug, Clone, Copy)]
+pub struct PlacementArea {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl PlacementArea {
+    fn size(&self) -> Vec2 {
+        self.max - self.min
+    }
+
+    fn random_point(&self, rng: &mut impl Rng) -> Vec2 {
+        glm::vec2(
+            rng.random_range(self.min.x..=self.max.x),
+            rng.random_range(self.min.y..=self.max.y),
+        )
+    }
+
+    fn contains(&self, point: Vec2) -> bool {
+        (self.min.x..=self.max.x).contains(&point.x) && (self.min.y..=self.max.y).contains(&point.y)
+    }
+}
+
+/// Grayscale image controlling the probability of placing a tree at a given spot,
+/// stretched over the whole placement area
+pub struct DensityMap {
+    values: Vec<f32>,
+    width: usize,
+    height: usize,
+}
+
+impl DensityMap {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let image = image::open(path)
+            .map_err(|e| format!("Failed to load density map {path}: {e}"))?
+            .to_luma32f();
+        let (width, height) = image.dimensions();
+
+        Ok(Self {
+            values: image.into_raw(),
+            width: width as usize,
+            height: height as usize,
+        })
+    }
+
+    /// Returns the density at `u`, `v` in the range 0..1, using the nearest pixel
+    #[allow(
+        clippy::cast_precision_loss,
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss
+    )]
+    fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.values[y * self.width + x]
+    }
+}
+
+/// Uniformly random positions, trees may overlap
+pub fn uniform(rng: &mut impl Rng, area: &PlacementArea, count: usize) -> Vec<Vec2> {
+    (0..count).map(|_| area.random_point(rng)).collect()
+}
+
+/// Random positions at least `min_spacing` apart (Bridson's algorithm).
+/// Returns fewer than `count` positions if the area cannot fit more.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+pub fn poisson_disk(
+    rng: &mut impl Rng,
+    area: &PlacementArea,
+    count: usize,
+    min_spacing: f32,
+) -> Vec<Vec2> {
+    if count == 0 {
+        return Vec::new();
+    }
+    if min_spacing <= 0.0 {
+        return uniform(rng, area, count);
+    }
+
+    // Background grid with cells small enough to hold at most one sample each
+    let cell_size = min_spacing / std::f32::consts::SQRT_2;
+    let grid_width = (area.size().x / cell_size).ceil() as usize + 1;
+    let grid_height = (area.size().y / cell_size).ceil() as usize + 1;
+    let mut grid: Vec<Option<usize>> = vec![None; grid_width * grid_height];
+    let cell_of = |point: &Vec2| {
+        let offset = (point - area.min) / cell_size;
+        (offset.x as usize, offset.y as usize)
+    };
+
+    let mut samples = Vec::with_capacity(count);
+    let mut active = Vec::new();
+
+    let first = area.random_point(rng);
+    let (column, row) = cell_of(&first);
+    grid[row * grid_width + column] = Some(0);
+    samples.push(first);
+    active.push(0);
+
+    while !active.is_empty() && samples.len() < count {
+        let active_index = rng.random_range(0..active.len());
+        let center = samples[active[active_index]];
+
+        let candidate = (0..POISSON_DISK_CANDIDATES)
+            .map(|_| {
+                let angle = rng.random_range(0.0..std::f32::consts::TAU);
+                let distance = rng.random_range(min_spacing..2.0 * min_spacing);
+                center + glm::vec2(angle.cos(), angle.sin()) * distance
+            })
+            .find(|candidate| {
+                if !area.contains(*candidate) {
+                    return false;
+                }
+                let (column, row) = cell_of(candidate);
+                let neighbours_rows = row.saturating_sub(2)..=(row + 2).min(grid_height - 1);
+                neighbours_rows.into_iter().all(|r| {
+                    (column.saturating_sub(2)..=(column + 2).min(grid_width - 1)).all(|c| {
+                        grid[r * grid_width + c].is_none_or(|neighbour| {
+                            glm::distance(&samples[neighbour], candidate) >= min_spacing
+                        })
+                    })
+                })
+            });
+
+        match candidate {
+            Some(candidate) => {
+                let (column, row) = cell_of(&candidate);
+                grid[row * grid_width + column] = Some(samples.len());
+                active.push(samples.len());
+                samples.push(candidate);
+            }
+            None => {
+                active.swap_remove(active_index);
+            }
+        }
+    }
+
+    samples
+}
+
+/// One tree per cell of a grid covering the area, at a random spot within its cell
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+pub fn jittered_grid(rng: &mut impl Rng, area: &PlacementArea, count: usize) -> Vec<Vec2> {
+    if count == 0 {
+        return Vec::new();
+    }
+
+    // Pick the number of columns so that cells are roughly square
+    let size = area.size();
+    let aspect_ratio = size.x / size.y.max(f32::EPSILON);
+    let columns = ((count as f32 * aspect_ratio).sqrt().ceil() as usize).clamp(1, count);
+    let rows = count.div_ceil(columns);
+    let cell_size = glm::vec2(size.x / columns as f32, size.y / rows as f32);
+
+    // With a partially filled grid, leave random cells empty rather than the last row
+    let mut cells: Vec<(usize, usize)> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect();
+    cells.shuffle(rng);
+    cells.truncate(count);
+
+    cells
+        .into_iter()
+        .map(|(column, row)| {
+            let jitter = glm::vec2(rng.random_range(0.0..=1.0), rng.random_range(0.0..=1.0));
+            area.min
+                + glm::vec2(
+                    (column as f32 + jitter.x) * cell_size.x,
+                    (row as f32 + jitter.y) * cell_size.y,
+                )
+        })
+        .collect()
+}
+
+/// Random positions accepted with probability given by the density map.
+/// Returns fewer than `count` positions if the map is too sparse.
+pub fn density_mapped(
+    rng: &mut impl Rng,
+    area: &PlacementArea,
+    count: usize,
+    density_map: &DensityMap,
+) -> Vec<Vec2> {
+    let size = area.size();
+    let mut positions = Vec::with_capacity(count);
+
+    for _ in 0..count * DENSITY_MAP_MAX_ATTEMPTS {
+        if positions.len() == count {
+            break;
+        }
+        let point = area.random_point(rng);
+        let uv = (point - area.min).component_div(&size);
+        if rng.random_range(0.0..1.0) < density_map.sample(uv.x, uv.y) {
+            positions.push(point);
+        }
+    }
+
+    positions
+}
+
+/// Picks a species for each of `count` trees with probability proportional to its weight.
+/// All trees get the first species if no weight is positive.
+pub fn assign_species(rng: &mut impl Rng, weights: &[f32], count: usize) -> Vec<usize> {
+    match WeightedIndex::new(weights.iter().map(|weight| weight.max(0.0))) {
+        Ok(distribution) => (0..count).map(|_| distribution.sample(rng)).collect(),
+        Err(_) => vec![0; count],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn test_area() -> PlacementArea {
+        PlacementArea {
+            min: glm::vec2(-10.0, -5.0),
+            max: glm::vec2(10.0, 5.0),
+        }
+    }
+
+    #[test]
+    fn test_placement_is_deterministic() {
+        let area = test_area();
+        let first = poisson_disk(&mut StdRng::seed_from_u64(7), &area, 20, 1.5);
+        let second = poisson_disk(&mut StdRng::seed_from_u64(7), &area, 20, 1.5);
+        assert_eq!(first, second);
+
+        let other_seed = poisson_disk(&mut StdRng::seed_from_u64(8), &area, 20, 1.5);
+        assert_ne!(first, other_seed);
+    }
+
+    #[test]
+    fn test_poisson_disk_respects_spacing() {
+        let area = test_area();
+        let positions = poisson_disk(&mut StdRng::seed_from_u64(1), &area, 25, 2.0);
+        assert_eq!(positions.len(), 25);
+
+        for (i, a) in positions.iter().enumerate() {
+            assert!(area.contains(*a));
+            for b in &positions[i + 1..] {
+                assert!(glm::distance(a, b) >= 2.0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_poisson_disk_stops_when_area_is_full() {
+        let positions = poisson_disk(&mut StdRng::seed_from_u64(1), &test_area(), 100, 8.0);
+        assert!(!positions.is_empty());
+        assert!(positions.len() < 100);
+    }
+
+    #[test]
+    fn test_jittered_grid_covers_area() {
+        let area = test_area();
+        let positions = jittered_grid(&mut StdRng::seed_from_u64(3), &area, 50);
+        assert_eq!(positions.len(), 50);
+        assert!(positions.iter().all(|p| area.contains(*p)));
+    }
+
+    #[test]
+    fn test_density_map_avoids_empty_areas() {
+        // Left half empty, right half full
+        let density_map = DensityMap {
+            values: vec![0.0, 1.0],
+            width: 2,
+            height: 1,
+        };
+        let positions = density_mapped(
+            &mut StdRng::seed_from_u64(5),
+            &test_area(),
+            30,
+            &density_map,
+        );
+        assert_eq!(positions.len(), 30);
+        assert!(positions.iter().all(|p| p.x >= 0.0));
+    }
+
+    #[test]
+    fn test_instance_variation_ranges() {
+        let variation = InstanceVariation {
+            min_scale: 0.5,
+            max_scale: 2.0,
+            max_tilt: 10.0,
+            iteration_jitter: 2,
+        };
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..100 {
+            let (transformation, iteration_offset) = variation.sample(&mut rng);
+            let up = (transformation * glm::vec4(0.0, 1.0, 0.0, 0.0)).xyz();
+            assert!((0.5..=2.0).contains(&up.norm()));
+            assert!(glm::angle(&up, &glm::vec3(0.0, 1.0, 0.0)) <= 10_f32.to_radians() + 1e-4);
+            assert!(iteration_offset <= 2);
+        }
+
+        let (transformation, iteration_offset) = InstanceVariation::default().sample(&mut rng);
+        let up = (transformation * glm::vec4(0.0, 1.0, 0.0, 0.0)).xyz();
+        assert!((up - glm::vec3(0.0, 1.0, 0.0)).norm() < 1e-5);
+        assert_eq!(iteration_offset, 0);
+    }
+
+    #[test]
+    fn test_assign_species_by_weight() {
+        let species = assign_species(&mut StdRng::seed_from_u64(2), &[3.0, 0.0, 1.0], 400);
+        assert!(species.iter().all(|&s| s != 1));
+        let first = species.iter().filter(|&&s| s == 0).count();
+        assert!((250..350).contains(&first));
+
+        assert_eq!(
+            assign_species(&mut StdRng::seed_from_u64(2), &[0.0, 0.0], 3),
+            [0, 0, 0]
+        );
+        assert_eq!(
+            assign_species(&mut StdRng::seed_from_u64(2), &[], 2),
+            [0, 0]
+        );
+    }
+}
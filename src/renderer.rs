@@ -1,25 +1,72 @@
 use crate::app::AppInteractionMode;
-use crate::gui::GuiController;
-use crate::scene::Scene;
+use crate::gui::{ExportRequest, GuiController, SceneRequest, ScreenshotRequest, SpeciesConfig};
+use crate::scene::{Scene, Species, WindParameters};
 use crate::shaders::make_shader_program;
+use crate::stats::PlantStats;
 
 use glium::glutin::surface::WindowSurface;
 use glium::{
-    Depth, DepthTest, Display, DrawParameters, Frame, Program, Surface, implement_vertex, uniform,
+    Depth, DepthTest, Display, DrawParameters, Program, Rect, Surface, implement_vertex, uniform,
 };
 
 use crate::camera::ViewParameters;
-use crate::model_loader::Model3D;
-use glm::{Mat4, Vec3};
-use tobj::Model;
+use crate::culling::Frustum;
+use crate::model_loader::{Model3D, TextureImage};
+use crate::picking::Inspection;
+use crate::screenshot::{self, TILE_SIZE};
+use glium::framebuffer::{DepthRenderBuffer, SimpleFrameBuffer};
+use glium::texture::{DepthFormat, MipmapsOption, RawImage2d, Texture2d, UncompressedFloatFormat};
+use glium::uniforms::SamplerWrapFunction;
+use glm::Mat4;
+use image::RgbaImage;
+use std::collections::HashMap;
+use std::time::Instant;
 use winit::event_loop::ActiveEventLoop;
 use winit::window::Window;
 
+const BACKGROUND_COLOR: (f32, f32, f32, f32) = (0.1, 0.1, 0.1, 1.0);
+
 pub struct Renderer {
     window: Window,
     display: Display<WindowSurface>,
     program: Program,
     gui: GuiController,
+    start_time: Instant,
+    stats: RenderStats,
+    /// Textures of the models uploaded to the GPU, by the path they were loaded from
+    textures: HashMap<String, Texture2d>,
+    /// Bound in place of the diffuse texture of untextured models
+    blank_texture: Texture2d,
+    /// Bound in place of the normal map of models without one, it keeps the mesh normals
+    flat_normal_texture: Texture2d,
+}
+
+/// Counters describing the most recently rendered frame
+#[derive(Debug, Default, Clone)]
+pub struct RenderStats {
+    pub drawn_trees: usize,
+    pub culled_trees: usize,
+    pub drawn_segments: usize,
+    /// Triangles of all drawn segments, without the floor
+    pub drawn_triangles: usize,
+}
+
+/// Look of leaves and other foliage models
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoliageSettings {
+    /// Texture alpha below which fragments of foliage are discarded
+    pub alpha_cutoff: f32,
+    /// Share of the light from behind that shines through foliage, 0 turns it off
+    pub translucency: f32,
+}
+
+impl Default for FoliageSettings {
+    fn default() -> Self {
+        Self {
+            alpha_cutoff: 0.5,
+            translucency: 0.0,
+        }
+    }
 }
 
 impl Renderer {
@@ -30,12 +77,23 @@ impl Renderer {
     ) -> Self {
         let program = make_shader_program(&display).expect("Failed to create shader program");
         let gui = GuiController::new(&display, &window, event_loop);
+        let pixel_texture = |pixel: [u8; 4]| {
+            Texture2d::new(&display, RawImage2d::from_raw_rgba(pixel.to_vec(), (1, 1)))
+                .expect("Failed to create texture")
+        };
+        let blank_texture = pixel_texture([255, 255, 255, 255]);
+        let flat_normal_texture = pixel_texture([128, 128, 255, 255]);
 
         Renderer {
             window,
             display,
             program,
             gui,
+            start_time: Instant::now(),
+            stats: RenderStats::default(),
+            textures: HashMap::new(),
+            blank_texture,
+            flat_normal_texture,
         }
     }
 
@@ -78,72 +136,304 @@ impl Renderer {
         }
     }
 
+    /// Draws the scene, or with a comparison species the scene twice side by side: the left half
+    /// as it is and the right half with the species at the given index replaced
     pub fn render_scene(
         &mut self,
         scene: &Scene,
         interaction_mode: &AppInteractionMode,
         view_parameters: &ViewParameters,
+        comparison: Option<(usize, &Species)>,
     ) {
         let mut frame = self.display.draw();
-        frame.clear_color_and_depth((0.1, 0.1, 0.1, 1.0), 1.0);
+        frame.clear_color_and_depth(BACKGROUND_COLOR, 1.0);
+
+        let time = self.elapsed_time();
+        self.stats = match comparison {
+            None => self.draw_scene(&mut frame, scene, view_parameters, None, None, time),
+            Some(comparison) => {
+                let (width, height) = frame.get_dimensions();
+                let half_width = width / 2;
+                let left = Rect {
+                    left: 0,
+                    bottom: 0,
+                    width: half_width,
+                    height,
+                };
+                let right = Rect {
+                    left: half_width,
+                    width: width - half_width,
+                    ..left
+                };
+                // The stats overlay describes variant A
+                let stats =
+                    self.draw_scene(&mut frame, scene, view_parameters, None, Some(left), time);
+                self.draw_scene(
+                    &mut frame,
+                    scene,
+                    view_parameters,
+                    Some(comparison),
+                    Some(right),
+                    time,
+                );
+                stats
+            }
+        };
 
+        let show_control_panel = *interaction_mode == AppInteractionMode::GuiInteraction;
+        if show_control_panel || self.gui.get_show_stats() {
+            self.gui.draw(
+                &self.window,
+                &self.display,
+                &mut frame,
+                show_control_panel,
+                &self.stats,
+            );
+        }
+
+        frame.finish().expect("Failed to destroy frame");
+    }
+
+    /// Seconds since the renderer was created, which drive the wind animation
+    pub fn elapsed_time(&self) -> f32 {
+        self.start_time.elapsed().as_secs_f32()
+    }
+
+    /// Renders the scene into an image of any size. The image is drawn in tiles into an
+    /// offscreen target, so its size is not limited by the window or the GPU.
+    pub fn render_image(
+        &mut self,
+        scene: &Scene,
+        view_parameters: &ViewParameters,
+        species_override: Option<(usize, &Species)>,
+        (width, height): (u32, u32),
+        time: f32,
+    ) -> Result<RgbaImage, String> {
+        let (tile_width, tile_height) = (width.min(TILE_SIZE), height.min(TILE_SIZE));
+        let texture = Texture2d::empty_with_format(
+            &self.display,
+            UncompressedFloatFormat::U8U8U8U8,
+            MipmapsOption::NoMipmap,
+            tile_width,
+            tile_height,
+        )
+        .map_err(|e| format!("Failed to create the offscreen target: {e}"))?;
+        let depth_buffer =
+            DepthRenderBuffer::new(&self.display, DepthFormat::I24, tile_width, tile_height)
+                .map_err(|e| format!("Failed to create the offscreen depth buffer: {e}"))?;
+        let mut framebuffer =
+            SimpleFrameBuffer::with_depth_buffer(&self.display, &texture, &depth_buffer)
+                .map_err(|e| format!("Failed to create the offscreen target: {e}"))?;
+
+        let mut image = RgbaImage::new(width, height);
+        let projection = Mat4::from(view_parameters.projection_matrix);
+        for tile in screenshot::tiles(width, height, TILE_SIZE) {
+            let tile_view = ViewParameters {
+                projection_matrix: screenshot::tile_projection(&projection, &tile, width, height)
+                    .into(),
+                ..*view_parameters
+            };
+            let viewport = Rect {
+                left: 0,
+                bottom: 0,
+                width: tile.width,
+                height: tile.height,
+            };
+            framebuffer.clear_color_and_depth(BACKGROUND_COLOR, 1.0);
+            self.draw_scene(
+                &mut framebuffer,
+                scene,
+                &tile_view,
+                species_override,
+                Some(viewport),
+                time,
+            );
+            let pixels: RawImage2d<u8> = texture.read();
+            screenshot::copy_tile(&mut image, &pixels.data, pixels.width, &tile);
+        }
+        Ok(image)
+    }
+
+    /// Reads back the last frame shown in the window, with the GUI drawn over it
+    pub fn read_window(&self) -> Result<RgbaImage, String> {
+        let pixels: RawImage2d<u8> = self
+            .display
+            .read_front_buffer()
+            .map_err(|e| format!("Failed to read the window: {e:?}"))?;
+        let mut image = RgbaImage::new(pixels.width, pixels.height);
+        let tile = screenshot::Tile {
+            x: 0,
+            y: 0,
+            width: pixels.width,
+            height: pixels.height,
+        };
+        screenshot::copy_tile(&mut image, &pixels.data, pixels.width, &tile);
+        Ok(image)
+    }
+
+    /// Draws the trees and the floor into the viewport, or the whole surface without one
+    #[allow(clippy::too_many_arguments)]
+    fn draw_scene(
+        &mut self,
+        surface: &mut impl Surface,
+        scene: &Scene,
+        view_parameters: &ViewParameters,
+        species_override: Option<(usize, &Species)>,
+        viewport: Option<Rect>,
+        time: f32,
+    ) -> RenderStats {
         let shading_mode = i32::from(*self.gui.get_shading_mode());
-        let (interpolation_color_low, interpolation_color_high) =
-            self.gui.get_interpolation_colors();
-
-        if !scene.transformations().is_empty() {
-            let instance_data: Vec<InstanceData> = scene
-                .transformations()
-                .iter()
-                .flat_map(|transform_list| {
-                    transform_list
-                        .iter()
-                        .map(|matrix| InstanceData::from_matrix(*matrix))
-                })
-                .collect();
 
+        let frustum = Frustum::from_view_projection(
+            &(Mat4::from(view_parameters.projection_matrix)
+                * Mat4::from(view_parameters.view_matrix)),
+        );
+        let frustum_culling = self.gui.get_frustum_culling();
+        let mut stats = RenderStats::default();
+
+        let instance_data = Self::tree_instances(
+            scene,
+            &frustum,
+            frustum_culling,
+            species_override,
+            &mut stats,
+        );
+
+        for (species_index, species_instances) in instance_data.iter().enumerate() {
+            if species_instances.is_empty() {
+                continue;
+            }
+            let species = Self::species_with_override(scene, species_index, species_override);
+            let (interpolation_color_low, interpolation_color_high) =
+                species.interpolation_colors();
             self.draw_model_instanced(
-                &mut frame,
-                scene.fractal_base(),
-                &instance_data,
+                surface,
+                species.fractal_base(),
+                species_instances,
                 view_parameters,
                 *scene.light_position(),
                 shading_mode,
-                scene.target_height(),
+                species.target_height(),
                 interpolation_color_low,
                 interpolation_color_high,
                 ColorMode::Interpolated,
+                scene.wind(),
+                self.gui.get_texture_tiling(),
+                viewport,
+                time,
             );
         }
 
         // There is overhead in using instanced rendering for a single instance
         // But it is simpler this way
-        let scale_matrix = glm::scale(&Mat4::identity(), &Vec3::new(10.0, 1.0, 10.0));
-        let floor_instance = vec![InstanceData::from_matrix(scale_matrix)];
+        let floor_instance = vec![InstanceData::from_matrix(Mat4::identity())];
         self.draw_model_instanced(
-            &mut frame,
+            surface,
             scene.floor(),
             &floor_instance,
             view_parameters,
             *scene.light_position(),
             shading_mode,
             1.0,
-            interpolation_color_low,
-            interpolation_color_high,
+            [0.0; 3],
+            [0.0; 3],
             ColorMode::Material,
+            &WindParameters::default(),
+            1.0,
+            viewport,
+            time,
         );
+        stats
+    }
 
-        if *interaction_mode == AppInteractionMode::GuiInteraction {
-            self.gui.draw(&self.window, &self.display, &mut frame);
+    fn species_with_override<'a>(
+        scene: &'a Scene,
+        species_index: usize,
+        species_override: Option<(usize, &'a Species)>,
+    ) -> &'a Species {
+        match species_override {
+            Some((index, species)) if index == species_index => species,
+            _ => &scene.species()[species_index],
         }
+    }
 
-        frame.finish().expect("Failed to destroy frame");
+    /// Builds the instances of all trees that pass frustum culling, grouped by species
+    fn tree_instances(
+        scene: &Scene,
+        frustum: &Frustum,
+        frustum_culling: bool,
+        species_override: Option<(usize, &Species)>,
+        stats: &mut RenderStats,
+    ) -> Vec<Vec<InstanceData>> {
+        // Trees are batched by species, as each one has its own base model and colors
+        let mut instance_data: Vec<Vec<InstanceData>> = vec![Vec::new(); scene.species().len()];
+        for (tree_index, ((displacement_matrix, bounding_box), &species_index)) in scene
+            .displacement_matrices
+            .iter()
+            .zip(scene.bounding_boxes())
+            .zip(scene.tree_species_indices())
+            .enumerate()
+        {
+            let Some(species_instances) = instance_data.get_mut(species_index) else {
+                continue;
+            };
+            let overridden = species_override.is_some_and(|(index, _)| index == species_index);
+            let species = Self::species_with_override(scene, species_index, species_override);
+            let (level, tree_matrix) = scene.tree_drawing(tree_index, species);
+            let segments = species.segments(level);
+            let bounding_box = if overridden {
+                &species.local_bounds().transformed(displacement_matrix)
+            } else {
+                bounding_box
+            };
+            let max_branch_order = segments.iter().map(|s| s.branch_order).max().unwrap_or(0);
+            let tree_scale = (0..3)
+                .map(|axis| tree_matrix.column(axis).xyz().norm())
+                .fold(0.0, f32::max);
+            let sway_margin = scene.wind().max_sway(max_branch_order, tree_scale);
+            if frustum_culling && !frustum.intersects(&bounding_box.inflated(sway_margin)) {
+                stats.culled_trees += 1;
+                continue;
+            }
+            stats.drawn_trees += 1;
+            stats.drawn_segments += segments.len();
+            stats.drawn_triangles += segments.len() * species.fractal_base().triangle_count();
+
+            let tree_origin: [f32; 3] = displacement_matrix.column(3).xyz().into();
+            let instance_scale = displacement_matrix.column(1).xyz().norm();
+            let selection = scene
+                .selection()
+                .filter(|selection| selection.tree_index == tree_index);
+            // Picked segments refer to the species in the scene, not to the one replacing it
+            let selected_segment = selection
+                .filter(|selection| !overridden && selection.level == level)
+                .map(|selection| selection.segment_index);
+            species_instances.extend(segments.iter().enumerate().map(
+                |(segment_index, segment)| {
+                    let highlight = if selected_segment == Some(segment_index) {
+                        Highlight::Segment
+                    } else if selection.is_some() {
+                        Highlight::Tree
+                    } else {
+                        Highlight::None
+                    };
+                    InstanceData::from_segment(
+                        tree_matrix * segment.transformation,
+                        segment.branch_order,
+                        tree_origin,
+                        instance_scale,
+                        highlight,
+                    )
+                },
+            ));
+        }
+        instance_data
     }
 
     #[allow(clippy::too_many_arguments)]
     pub fn draw_model_instanced(
         &mut self,
-        frame: &mut Frame,
+        surface: &mut impl Surface,
         model: &Model3D,
         instance_data: &[InstanceData],
         view_parameters: &ViewParameters,
@@ -153,8 +443,26 @@ impl Renderer {
         interpolation_color_low: [f32; 3],
         interpolation_color_high: [f32; 3],
         color_mode: ColorMode,
+        wind: &WindParameters,
+        texture_tiling: f32,
+        viewport: Option<Rect>,
+        time: f32,
     ) {
-        let (vertices, indices) = Self::model_to_vertices_and_indices(&model.geometry);
+        let (vertices, indices) = Self::model_to_vertices_and_indices(model);
+        for texture in [&model.diffuse_texture, &model.normal_texture]
+            .into_iter()
+            .flatten()
+        {
+            self.upload_texture(texture);
+        }
+        let texture = |texture: &Option<TextureImage>| {
+            texture
+                .as_ref()
+                .and_then(|texture| self.textures.get(&texture.path))
+        };
+        let diffuse_texture = texture(&model.diffuse_texture);
+        let normal_texture = texture(&model.normal_texture);
+        let foliage = self.gui.get_foliage_settings();
 
         let vertex_buffer = &glium::VertexBuffer::new(&self.display, &vertices).unwrap();
         let instance_buffer = glium::VertexBuffer::new(&self.display, instance_data).unwrap();
@@ -171,6 +479,7 @@ impl Renderer {
                 write: true,
                 ..Depth::default()
             },
+            viewport,
             ..DrawParameters::default()
         };
 
@@ -187,9 +496,27 @@ impl Renderer {
             u_material_ambient: model.material.ambient.unwrap(),
             u_material_diffuse: model.material.diffuse.unwrap(),
             u_material_specular: model.material.specular.unwrap(),
+            u_time: time,
+            u_wind_direction: wind.direction_vector(),
+            u_wind_strength: wind.strength,
+            u_wind_frequency: wind.gust_frequency,
+            u_has_diffuse_texture: diffuse_texture.is_some(),
+            u_diffuse_texture: diffuse_texture
+                .unwrap_or(&self.blank_texture)
+                .sampled()
+                .wrap_function(SamplerWrapFunction::Repeat),
+            u_has_normal_texture: normal_texture.is_some(),
+            u_normal_texture: normal_texture
+                .unwrap_or(&self.flat_normal_texture)
+                .sampled()
+                .wrap_function(SamplerWrapFunction::Repeat),
+            u_texture_tiling: texture_tiling,
+            u_foliage: model.foliage,
+            u_alpha_cutoff: foliage.alpha_cutoff,
+            u_translucency: foliage.translucency,
         };
 
-        frame
+        surface
             .draw(
                 (vertex_buffer, instance_buffer.per_instance().unwrap()),
                 index_buffer,
@@ -200,19 +527,43 @@ impl Renderer {
             .expect("Failed to draw frame");
     }
 
-    fn model_to_vertices_and_indices(model: &Model) -> (Vec<Vertex>, Vec<u32>) {
-        let mesh = &model.mesh;
+    /// Uploads the texture to the GPU unless it already is
+    fn upload_texture(&mut self, texture: &TextureImage) {
+        if self.textures.contains_key(&texture.path) {
+            return;
+        }
+        let image = &texture.image;
+        // OpenGL expects the bottom row first
+        let raw = RawImage2d::from_raw_rgba_reversed(image.as_raw(), image.dimensions());
+        match Texture2d::new(&self.display, raw) {
+            Ok(uploaded) => {
+                self.textures.insert(texture.path.clone(), uploaded);
+            }
+            Err(e) => log::warn!("Failed to upload texture {}: {e}", texture.path),
+        }
+    }
+
+    fn model_to_vertices_and_indices(model: &Model3D) -> (Vec<Vertex>, Vec<u32>) {
+        let mesh = &model.geometry.mesh;
         let positions = &mesh.positions;
         let normals = &mesh.normals;
+        let texcoords = &mesh.texcoords;
 
         assert_eq!(positions.len() % 3, 0);
         assert_eq!(positions.len(), normals.len());
         let n_vertices = positions.len() / 3;
+        let has_texcoords = texcoords.len() == n_vertices * 2;
 
         let vertices: Vec<Vertex> = (0..n_vertices)
             .map(|i| Vertex {
                 position: [positions[i * 3], positions[i * 3 + 1], positions[i * 3 + 2]],
                 normal: [normals[i * 3], normals[i * 3 + 1], normals[i * 3 + 2]],
+                tex_coords: if has_texcoords {
+                    [texcoords[i * 2], texcoords[i * 2 + 1]]
+                } else {
+                    [0.0; 2]
+                },
+                tangent: model.tangents[i],
             })
             .collect();
 
@@ -228,26 +579,141 @@ impl Renderer {
     pub fn unset_requires_tree_regeneration(&mut self) {
         self.gui.unset_requires_tree_regeneration();
     }
+
+    pub fn set_terrain_error(&mut self, error: Option<String>) {
+        self.gui.set_terrain_error(error);
+    }
+
+    pub fn set_generation_progress(&mut self, progress: Option<f32>) {
+        self.gui.set_generation_progress(progress);
+    }
+
+    pub fn set_plant_stats(&mut self, plant_stats: Vec<PlantStats>) {
+        self.gui.set_plant_stats(plant_stats);
+    }
+
+    pub fn set_growth_error(&mut self, error: Option<String>) {
+        self.gui.set_growth_error(error);
+    }
+
+    pub fn set_placement_error(&mut self, error: Option<String>) {
+        self.gui.set_placement_error(error);
+    }
+
+    pub fn set_inspection(&mut self, inspection: Option<Inspection>) {
+        self.gui.set_inspection(inspection);
+    }
+
+    pub fn take_scene_request(&mut self) -> Option<SceneRequest> {
+        self.gui.take_scene_request()
+    }
+
+    pub fn set_scene_status(&mut self, status: Result<String, String>) {
+        self.gui.set_scene_status(status);
+    }
+
+    pub fn set_species(&mut self, species: Vec<SpeciesConfig>) {
+        self.gui.set_species(species);
+    }
+
+    pub fn take_screenshot_request(&mut self) -> Option<ScreenshotRequest> {
+        self.gui.take_screenshot_request()
+    }
+
+    pub fn set_screenshot_status(&mut self, status: Result<String, String>) {
+        self.gui.set_screenshot_status(status);
+    }
+
+    pub fn take_export_request(&mut self) -> Option<ExportRequest> {
+        self.gui.take_export_request()
+    }
+
+    pub fn set_export_progress(&mut self, progress: Option<(u32, u32)>) {
+        self.gui.set_export_progress(progress);
+    }
+
+    pub fn set_export_status(&mut self, status: Result<String, String>) {
+        self.gui.set_export_status(status);
+    }
+
+    pub fn set_layout_edited(&mut self, edited: bool) {
+        self.gui.set_layout_edited(edited);
+    }
+
+    pub fn window_size(&self) -> (u32, u32) {
+        self.window.inner_size().into()
+    }
+
+    pub fn advance_growth_animation(&mut self, delta_seconds: f32) {
+        self.gui.advance_growth_animation(delta_seconds);
+    }
 }
 
 #[derive(Copy, Clone)]
 pub struct Vertex {
     position: [f32; 3],
     normal: [f32; 3],
+    tex_coords: [f32; 2],
+    tangent: [f32; 3],
 }
-implement_vertex!(Vertex, position, normal);
+implement_vertex!(Vertex, position, normal, tex_coords, tangent);
 
 #[derive(Copy, Clone)]
 pub struct InstanceData {
     model_matrix: [[f32; 4]; 4],
+    branch_order: f32,
+    tree_origin: [f32; 3],
+    /// Scale the tree is placed with, which its height gradient is normalized by
+    instance_scale: f32,
+    highlight: f32,
 }
 
-implement_vertex!(InstanceData, model_matrix);
+implement_vertex!(
+    InstanceData,
+    model_matrix,
+    branch_order,
+    tree_origin,
+    instance_scale,
+    highlight
+);
 
 impl InstanceData {
     fn from_matrix(matrix: Mat4) -> Self {
+        Self::from_segment(matrix, 0, [0.0; 3], 1.0, Highlight::None)
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn from_segment(
+        matrix: Mat4,
+        branch_order: u32,
+        tree_origin: [f32; 3],
+        instance_scale: f32,
+        highlight: Highlight,
+    ) -> Self {
         Self {
             model_matrix: matrix.into(),
+            branch_order: branch_order as f32,
+            tree_origin,
+            instance_scale,
+            highlight: f32::from(highlight),
+        }
+    }
+}
+
+/// Emphasis of an instance picked with the mouse
+#[derive(Clone, Copy)]
+enum Highlight {
+    None,
+    Tree,
+    Segment,
+}
+
+impl From<Highlight> for f32 {
+    fn from(highlight: Highlight) -> Self {
+        match highlight {
+            Highlight::None => 0.0,
+            Highlight::Tree => 1.0,
+            Highlight::Segment => 2.0,
         }
     }
 }
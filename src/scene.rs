@@ -1,67 +1,235 @@
+use crate::culling::Aabb;
+use crate::layout::TreeLayout;
+use crate::lod::{LodSettings, MAX_LOD_LEVELS, select_lod_level};
+use crate::lsystem::GrowthStage;
 use crate::model_loader::Model3D;
-use glm::{Mat4, Vec4};
-use tobj::Model;
+use crate::picking::{BoundingCylinder, Selection};
+use crate::terrain::Terrain;
+use crate::turtle::Segment;
+use glm::{Mat4, Vec3, Vec4};
+use tobj::{Material, Model};
 
 pub struct Scene {
+    terrain: Terrain,
+    /// Mesh of the terrain in world space
     floor: Model3D,
-    fractal_base: Model3D,
-    transformations: Vec<Vec<Mat4>>,
+    species: Vec<Species>,
+    /// Index of the species of every tree, in the same order as `displacement_matrices`
+    tree_species: Vec<usize>,
+    /// Number of derivation steps every tree lacks compared to its species
+    tree_iteration_offsets: Vec<u32>,
+    /// Level of detail currently used by every tree
+    lod_selection: Vec<usize>,
+    /// World-space bounding box of every tree, in the same order as `displacement_matrices`
+    bounding_boxes: Vec<Aabb>,
     pub displacement_matrices: Vec<Mat4>,
-    target_height: f32,
+    /// Segment picked with the mouse, highlighted when drawn
+    selection: Option<Selection>,
     light_position: [f32; 3],
+    wind: WindParameters,
 }
 
-impl Scene {
+/// Plant shared by all trees of one kind
+#[derive(Clone)]
+pub struct Species {
+    fractal_base: Model3D,
+    /// Cylinder around the base model, which segments are picked with
+    bounding_cylinder: BoundingCylinder,
+    /// Segments of the plant in its local space, one list per derivation step the plant lacks
+    /// starting from the fully derived one. Younger trees use the later levels as their own,
+    /// levels of detail use the ones following a tree's own level.
+    levels: Vec<Vec<Segment>>,
+    /// How far the plant was grown for every level
+    level_stages: Vec<GrowthStage>,
+    /// Height of every level, which all share the scale of the first one
+    level_heights: Vec<f32>,
+    /// Number of levels a tree may be coarsened by for its level of detail, plus one
+    n_lod_levels: usize,
+    /// Box around all levels in the plant's local space, at the sizes they are drawn at
+    local_bounds: Aabb,
+    target_height: f32,
+    /// Scale the first level was fitted to the target height with
+    scale_factor: f32,
+    interpolation_color_low: [f32; 3],
+    interpolation_color_high: [f32; 3],
+}
+
+/// Global wind affecting all trees in the scene
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindParameters {
+    /// Direction the wind blows towards, in degrees around the Y axis
+    pub direction: f32,
+    pub strength: f32,
+    /// Number of gusts per second
+    pub gust_frequency: f32,
+}
+
+impl Default for WindParameters {
+    fn default() -> Self {
+        Self {
+            direction: 0.0,
+            strength: 0.0,
+            gust_frequency: 0.5,
+        }
+    }
+}
+
+impl WindParameters {
+    /// Unit vector of the wind direction in the XZ plane
+    pub fn direction_vector(&self) -> [f32; 2] {
+        let radians = self.direction.to_radians();
+        [radians.cos(), radians.sin()]
+    }
+
+    /// Upper bound of the sway computed in `vertex.glsl` for a fully grown segment of a tree
+    /// scaled by `tree_scale`. The sway grows with the square of the height relative to the
+    /// species' target height, which taller trees exceed.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn max_sway(&self, branch_order: u32, tree_scale: f32) -> f32 {
+        let branch_order = branch_order as f32;
+        let relative_height = tree_scale.max(1.0);
+        self.strength
+            * relative_height
+            * relative_height
+            * (1.0 + 0.5 * branch_order)
+            * (1.0 + 0.1 * branch_order)
+    }
+}
+
+impl Species {
+    /// Creates a species from levels that each lack one more derivation step, starting from
+    /// `n_iterations`. The first is fitted to the target height, the others keep its scale,
+    /// so they are smaller. Up to `MAX_LOD_LEVELS` levels following a tree's own one serve as
+    /// its levels of detail.
     pub fn new(
-        floor: Model3D,
         fractal_base: Model3D,
-        transformations: Vec<Vec<Mat4>>,
-        displacement_matrices: Vec<Mat4>,
+        levels: Vec<Vec<Segment>>,
+        n_iterations: u32,
         target_height: f32,
-        light_position: [f32; 3],
+        interpolation_colors: ([f32; 3], [f32; 3]),
     ) -> Self {
-        Self {
-            floor,
+        let scale_factor = levels.first().map_or(1.0, |segments| {
+            Scene::height_scale_factor(&fractal_base, segments, target_height)
+        });
+        let stages = (0_u32..)
+            .take(levels.len())
+            .map(|level| GrowthStage::Iterations(n_iterations.saturating_sub(level)))
+            .collect();
+        let mut species = Self {
+            bounding_cylinder: BoundingCylinder::from_model(&fractal_base.geometry),
             fractal_base,
-            transformations,
-            displacement_matrices,
+            levels: Vec::new(),
+            level_stages: Vec::new(),
+            level_heights: Vec::new(),
+            n_lod_levels: 1,
+            local_bounds: Aabb::empty(),
             target_height,
-            light_position,
-        }
-    }
-
-    pub fn floor(&self) -> &Model3D {
-        &self.floor
+            scale_factor: 1.0,
+            interpolation_color_low: interpolation_colors.0,
+            interpolation_color_high: interpolation_colors.1,
+        };
+        species.set_levels(levels, stages, scale_factor, MAX_LOD_LEVELS as usize);
+        species
     }
 
     pub fn fractal_base(&self) -> &Model3D {
         &self.fractal_base
     }
 
-    pub fn transformations(&self) -> &Vec<Vec<Mat4>> {
-        &self.transformations
+    pub fn bounding_cylinder(&self) -> &BoundingCylinder {
+        &self.bounding_cylinder
     }
 
     pub fn target_height(&self) -> f32 {
         self.target_height
     }
 
-    pub fn light_position(&self) -> &[f32; 3] {
-        &self.light_position
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
     }
 
-    pub fn update_transformations(&mut self, transformations: Vec<Vec<Mat4>>, target_height: f32) {
-        let scaled_transformations = Self::scale_transformations_to_height(
-            transformations,
-            target_height,
-            &self.fractal_base.geometry,
-        );
-        self.transformations = scaled_transformations;
-        self.target_height = target_height;
+    pub fn local_bounds(&self) -> &Aabb {
+        &self.local_bounds
+    }
+
+    pub fn interpolation_colors(&self) -> ([f32; 3], [f32; 3]) {
+        (self.interpolation_color_low, self.interpolation_color_high)
+    }
+
+    /// Segments at the given level, or the last ones if there is no such level
+    pub fn segments(&self, level: usize) -> &[Segment] {
+        self.levels
+            .get(level)
+            .or(self.levels.last())
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// How far the plant was grown for the given level, or the last one if there is no such level
+    pub fn level_stage(&self, level: usize) -> Option<GrowthStage> {
+        self.level_stages
+            .get(level)
+            .or(self.level_stages.last())
+            .copied()
+    }
+
+    /// Returns the level a tree lacking `iteration_offset` derivation steps is drawn with at
+    /// the level of detail `lod_level`, and the scale that brings that level to the tree's size
+    pub fn level_for(&self, iteration_offset: usize, lod_level: usize) -> (usize, f32) {
+        let last = self.levels.len().saturating_sub(1);
+        let own = iteration_offset.min(last);
+        let drawn = (own + lod_level.min(self.n_lod_levels.saturating_sub(1))).min(last);
+        (drawn, self.level_scale(own, drawn))
+    }
+
+    /// Scale that makes the `drawn` level as tall as the `own` level
+    fn level_scale(&self, own: usize, drawn: usize) -> f32 {
+        match (self.level_heights.get(own), self.level_heights.get(drawn)) {
+            (Some(&own_height), Some(&drawn_height)) if drawn_height > 0.0 => {
+                own_height / drawn_height
+            }
+            _ => 1.0,
+        }
+    }
+
+    fn set_levels(
+        &mut self,
+        levels: Vec<Vec<Segment>>,
+        stages: Vec<GrowthStage>,
+        scale_factor: f32,
+        n_lod_levels: usize,
+    ) {
+        self.level_stages = stages;
+        self.levels = levels
+            .into_iter()
+            .map(|segments| Self::scale_transformations(segments, scale_factor))
+            .collect();
+        self.level_heights = self
+            .levels
+            .iter()
+            .map(|segments| Self::fractal_total_height(&self.fractal_base.geometry, segments))
+            .collect();
+        self.n_lod_levels = n_lod_levels;
+        self.scale_factor = scale_factor;
+        self.update_local_bounds();
     }
 
-    pub fn set_fractal_base(&mut self, model: Model3D) {
-        self.fractal_base = model;
+    fn update_local_bounds(&mut self) {
+        let model_bounds = Aabb::from_model(&self.fractal_base.geometry);
+
+        // Levels differ in shape and are scaled up when drawn in place of finer ones, so the
+        // box has to fit all of them at their largest
+        self.local_bounds = Aabb::empty();
+        for (level, segments) in self.levels.iter().enumerate() {
+            let first_own = level.saturating_sub(self.n_lod_levels.saturating_sub(1));
+            let max_scale = (first_own..=level)
+                .map(|own| self.level_scale(own, level))
+                .fold(1.0, f32::max);
+            let scaling = glm::scaling(&glm::vec3(max_scale, max_scale, max_scale));
+            for segment in segments {
+                self.local_bounds
+                    .union(&model_bounds.transformed(&(scaling * segment.transformation)));
+            }
+        }
     }
 
     /// Returns the maximum Y coordinate of the model's vertices
@@ -74,7 +242,7 @@ impl Scene {
             .fold(f32::NEG_INFINITY, f32::max)
     }
 
-    fn fractal_total_height(base: &Model, transformations: &[Vec<Mat4>]) -> f32 {
+    fn fractal_total_height(base: &Model, transformations: &[Segment]) -> f32 {
         if transformations.is_empty() {
             return 0.0;
         }
@@ -84,20 +252,11 @@ impl Scene {
 
         transformations
             .iter()
-            .flat_map(|mat_list| mat_list.iter().map(|mat| (mat * up_vector)[1]))
+            .map(|segment| (segment.transformation * up_vector)[1])
             .fold(f32::NEG_INFINITY, f32::max)
     }
 
-    fn scale_transformations_to_height(
-        transformations: Vec<Vec<Mat4>>,
-        target_height: f32,
-        base_model: &Model,
-    ) -> Vec<Vec<Mat4>> {
-        let current_height = Self::fractal_total_height(base_model, &transformations);
-        if current_height == 0.0 {
-            return transformations;
-        }
-        let scale_factor = target_height / current_height;
+    fn scale_transformations(transformations: Vec<Segment>, scale_factor: f32) -> Vec<Segment> {
         let scale_matrix = glm::scale(
             &Mat4::identity(),
             &glm::vec3(scale_factor, scale_factor, scale_factor),
@@ -105,7 +264,305 @@ impl Scene {
 
         transformations
             .into_iter()
-            .map(|mat_list| mat_list.into_iter().map(|mat| scale_matrix * mat).collect())
+            .map(|segment| Segment {
+                transformation: scale_matrix * segment.transformation,
+                ..segment
+            })
             .collect()
     }
 }
+
+impl Scene {
+    pub fn new(terrain: Terrain, floor_material: Material, light_position: [f32; 3]) -> Self {
+        Self {
+            floor: terrain.to_model(floor_material),
+            terrain,
+            species: Vec::new(),
+            tree_species: Vec::new(),
+            tree_iteration_offsets: Vec::new(),
+            lod_selection: Vec::new(),
+            bounding_boxes: Vec::new(),
+            displacement_matrices: Vec::new(),
+            selection: None,
+            light_position,
+            wind: WindParameters::default(),
+        }
+    }
+
+    pub fn floor(&self) -> &Model3D {
+        &self.floor
+    }
+
+    pub fn terrain(&self) -> &Terrain {
+        &self.terrain
+    }
+
+    /// Replaces the terrain and moves the base of every tree onto its surface
+    pub fn set_terrain(&mut self, terrain: Terrain) {
+        self.floor = terrain.to_model(self.floor.material.clone());
+        self.terrain = terrain;
+
+        for displacement_matrix in &mut self.displacement_matrices {
+            let (x, z) = (displacement_matrix[(0, 3)], displacement_matrix[(2, 3)]);
+            displacement_matrix[(1, 3)] = self.terrain.height_at(x, z);
+        }
+        self.update_bounding_boxes();
+    }
+
+    pub fn species(&self) -> &[Species] {
+        &self.species
+    }
+
+    /// Replaces the species at the given index, or appends it if the index is one past the end
+    pub fn set_species(&mut self, species_index: usize, species: Species) {
+        if species_index < self.species.len() {
+            self.species[species_index] = species;
+        } else {
+            self.species.push(species);
+        }
+        self.update_bounding_boxes();
+    }
+
+    /// Removes species past the given count, trees of removed species are not drawn until they
+    /// are remapped
+    pub fn truncate_species(&mut self, count: usize) {
+        self.species.truncate(count);
+        self.update_bounding_boxes();
+    }
+
+    pub fn set_species_colors(&mut self, species_index: usize, colors: ([f32; 3], [f32; 3])) {
+        if let Some(species) = self.species.get_mut(species_index) {
+            (
+                species.interpolation_color_low,
+                species.interpolation_color_high,
+            ) = colors;
+        }
+    }
+
+    /// Replaces the trees with new ones
+    pub fn set_trees(&mut self, layout: TreeLayout) {
+        debug_assert_eq!(
+            layout.displacement_matrices.len(),
+            layout.tree_species.len()
+        );
+        debug_assert_eq!(
+            layout.displacement_matrices.len(),
+            layout.iteration_offsets.len()
+        );
+        self.displacement_matrices = layout.displacement_matrices;
+        self.tree_species = layout.tree_species;
+        self.tree_iteration_offsets = layout.iteration_offsets;
+        self.lod_selection.clear();
+        self.selection = None;
+        self.update_bounding_boxes();
+    }
+
+    /// Moves every tree to the species at the index given for its own, see
+    /// `TreeLayout::remap_species`
+    pub fn remap_tree_species(&mut self, remapping: &[Option<usize>]) {
+        let unchanged = (0..)
+            .zip(remapping)
+            .all(|(species_index, &mapped)| mapped == Some(species_index));
+        if !unchanged {
+            let mut layout = self.layout();
+            layout.remap_species(remapping);
+            self.set_trees(layout);
+        }
+    }
+
+    pub fn layout(&self) -> TreeLayout {
+        TreeLayout {
+            displacement_matrices: self.displacement_matrices.clone(),
+            tree_species: self.tree_species.clone(),
+            iteration_offsets: self.tree_iteration_offsets.clone(),
+        }
+    }
+
+    /// Adds a fully derived tree of the given species
+    pub fn add_tree(&mut self, displacement_matrix: Mat4, species_index: usize) {
+        self.displacement_matrices.push(displacement_matrix);
+        self.tree_species.push(species_index);
+        self.tree_iteration_offsets.push(0);
+        self.update_bounding_boxes();
+    }
+
+    pub fn remove_tree(&mut self, tree_index: usize) {
+        if tree_index >= self.displacement_matrices.len() {
+            return;
+        }
+        self.displacement_matrices.remove(tree_index);
+        self.tree_species.remove(tree_index);
+        self.tree_iteration_offsets.remove(tree_index);
+        if tree_index < self.lod_selection.len() {
+            self.lod_selection.remove(tree_index);
+        }
+        self.selection = None;
+        self.update_bounding_boxes();
+    }
+
+    pub fn set_displacement_matrix(&mut self, tree_index: usize, displacement_matrix: Mat4) {
+        if let Some(matrix) = self.displacement_matrices.get_mut(tree_index) {
+            *matrix = displacement_matrix;
+            self.update_bounding_boxes();
+        }
+    }
+
+    /// Returns the species of the tree at the given index
+    pub fn tree_species(&self, tree_index: usize) -> Option<&Species> {
+        self.tree_species
+            .get(tree_index)
+            .and_then(|&species_index| self.species.get(species_index))
+    }
+
+    /// Returns the index of the species of every tree
+    pub fn tree_species_indices(&self) -> &[usize] {
+        &self.tree_species
+    }
+
+    /// Returns the level of the species' segments the tree at the given index is drawn with,
+    /// and the matrix that places them. Trees lacking derivation steps use the species derived
+    /// correspondingly fewer times as their own level, and its following levels as their
+    /// levels of detail, scaled up to the size of their own level.
+    pub fn tree_drawing(&self, tree_index: usize, species: &Species) -> (usize, Mat4) {
+        let iteration_offset = self
+            .tree_iteration_offsets
+            .get(tree_index)
+            .map_or(0, |&offset| offset as usize);
+        let lod_level = self.lod_selection.get(tree_index).copied().unwrap_or(0);
+        let (level, scale) = species.level_for(iteration_offset, lod_level);
+        let matrix =
+            self.displacement_matrices[tree_index] * glm::scaling(&glm::vec3(scale, scale, scale));
+        (level, matrix)
+    }
+
+    /// Largest number of derivation steps any tree lacks
+    pub fn max_iteration_offset(&self) -> u32 {
+        self.tree_iteration_offsets
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn selection(&self) -> Option<&Selection> {
+        self.selection.as_ref()
+    }
+
+    pub fn set_selection(&mut self, selection: Option<Selection>) {
+        self.selection = selection;
+    }
+
+    pub fn bounding_boxes(&self) -> &Vec<Aabb> {
+        &self.bounding_boxes
+    }
+
+    pub fn light_position(&self) -> &[f32; 3] {
+        &self.light_position
+    }
+
+    pub fn wind(&self) -> &WindParameters {
+        &self.wind
+    }
+
+    pub fn set_wind(&mut self, wind: WindParameters) {
+        self.wind = wind;
+    }
+
+    /// Updates transformations of a species using a precomputed scale factor instead of fitting
+    /// them to the target height, so that a partially grown plant keeps the scale of the fully
+    /// grown one. The levels are the plant at the same time for trees lacking 0, 1, ...
+    /// derivation steps, without coarser levels of detail, grown as far as their stages say.
+    pub fn update_transformations_with_scale(
+        &mut self,
+        species_index: usize,
+        levels: Vec<Vec<Segment>>,
+        stages: Vec<GrowthStage>,
+        scale_factor: f32,
+    ) {
+        if let Some(species) = self.species.get_mut(species_index) {
+            species.set_levels(levels, stages, scale_factor, 1);
+            self.update_bounding_boxes();
+        }
+    }
+
+    /// Returns the scale factor that fits the given transformations of a plant built from
+    /// `fractal_base` to the target height
+    pub fn height_scale_factor(
+        fractal_base: &Model3D,
+        transformations: &[Segment],
+        target_height: f32,
+    ) -> f32 {
+        let current_height = Species::fractal_total_height(&fractal_base.geometry, transformations);
+        if current_height == 0.0 {
+            return 1.0;
+        }
+        target_height / current_height
+    }
+
+    /// Picks the level of detail of every tree based on its distance from the camera
+    pub fn update_lod_selection(&mut self, camera_position: &Vec3, settings: &LodSettings) {
+        self.lod_selection
+            .resize(self.displacement_matrices.len(), 0);
+        for (tree_index, displacement_matrix) in self.displacement_matrices.iter().enumerate() {
+            let n_levels = match self.tree_species(tree_index) {
+                Some(species) if settings.enabled => species.n_lod_levels,
+                _ => 1,
+            };
+
+            let tree_position = displacement_matrix.column(3).xyz();
+            let distance = glm::distance(&tree_position, camera_position);
+            let selection = &mut self.lod_selection[tree_index];
+            *selection = select_lod_level(*selection, distance, settings.bias, n_levels);
+        }
+    }
+
+    fn update_bounding_boxes(&mut self) {
+        self.bounding_boxes = self
+            .displacement_matrices
+            .iter()
+            .enumerate()
+            .map(|(tree_index, displacement_matrix)| {
+                self.tree_species(tree_index)
+                    .map_or(Aabb::empty(), |species| {
+                        species.local_bounds.transformed(displacement_matrix)
+                    })
+            })
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ModelSelection;
+    use crate::model_loader::load_model;
+    use crate::symbol::Alphabet;
+    use crate::turtle::TurtleInterpreter;
+
+    #[test]
+    fn test_levels_keep_the_size_of_the_tree() {
+        let mut alphabet = Alphabet::default();
+        let levels = ["FFFF", "FF", "F"]
+            .map(|text| TurtleInterpreter::interpret(alphabet.parse(text), &alphabet, 30.0));
+        // The branch model is as tall as the segments are long
+        let species = Species::new(
+            load_model(ModelSelection::Branch),
+            levels.to_vec(),
+            4,
+            4.0,
+            ([0.0; 3], [1.0; 3]),
+        );
+
+        // Younger trees are smaller, coarser levels of detail are scaled up to the tree's size
+        assert_eq!(species.level_for(0, 0), (0, 1.0));
+        assert_eq!(species.level_for(1, 0), (1, 1.0));
+        assert_eq!(species.level_for(0, 1), (1, 2.0));
+        assert_eq!(species.level_for(1, 1), (2, 2.0));
+        // Offsets and levels of detail past the last level are clamped to it
+        assert_eq!(species.level_for(5, 0), (2, 1.0));
+        assert_eq!(species.level_for(0, 7), (2, 4.0));
+        assert!(species.local_bounds().max.y >= 4.0 - 1e-4);
+        assert_eq!(species.level_stage(1), Some(GrowthStage::Iterations(3)));
+        assert_eq!(species.level_stage(9), Some(GrowthStage::Iterations(2)));
+    }
+}
@@ -0,0 +1,247 @@
+use crate::common::ModelSelection;
+use crate::gui::{PresetSelection, SpeciesConfig};
+use crate::layout::TreeLayout;
+use std::fmt::Write;
+use std::path::Path;
+
+const TITLE: &str = "# L-system scene: species followed by the trees placed in it";
+
+const MODELS: [ModelSelection; 6] = [
+    ModelSelection::Cylinder,
+    ModelSelection::Branch,
+    ModelSelection::Leaf,
+    ModelSelection::LeafCard,
+    ModelSelection::Twig,
+    ModelSelection::Monkey,
+];
+
+const PRESETS: [PresetSelection; 5] = [
+    PresetSelection::Tree3D,
+    PresetSelection::Bush,
+    PresetSelection::Seaweed,
+    PresetSelection::TrunkTree,
+    PresetSelection::Custom,
+];
+
+/// Species of a scene together with the trees placed in it, which refer to the species by
+/// their index
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneFile {
+    pub species: Vec<SpeciesConfig>,
+    pub layout: TreeLayout,
+}
+
+/// Part of the file the lines belong to
+enum Section {
+    Start,
+    Species,
+    Trees,
+}
+
+impl SceneFile {
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{TITLE}\n");
+        for species in &self.species {
+            let lsystem_config = &species.lsystem_config;
+            let _ = writeln!(text, "\n[species]");
+            let _ = writeln!(text, "name = {}", species.name);
+            let _ = writeln!(text, "preset = {:?}", species.preset_selection);
+            let _ = writeln!(text, "model = {:?}", species.model_selection);
+            let _ = writeln!(text, "axiom = {}", lsystem_config.axiom);
+            for (predecessor, successor) in &lsystem_config.production_rules {
+                let _ = writeln!(text, "rule = {predecessor} -> {successor}");
+            }
+            let _ = writeln!(text, "iterations = {}", lsystem_config.n_iterations);
+            let _ = writeln!(text, "angle = {}", lsystem_config.angle);
+            let _ = writeln!(text, "height = {}", lsystem_config.fractal_height);
+            for (symbol, terminal_age) in &lsystem_config.terminal_ages {
+                let _ = writeln!(text, "terminal_age = {symbol} {terminal_age}");
+            }
+            let [r, g, b] = species.interpolation_color_low;
+            let _ = writeln!(text, "color_low = {r} {g} {b}");
+            let [r, g, b] = species.interpolation_color_high;
+            let _ = writeln!(text, "color_high = {r} {g} {b}");
+            let _ = writeln!(text, "weight = {}", species.weight);
+        }
+        let _ = write!(text, "\n[trees]\n{}", self.layout.to_text());
+        text
+    }
+
+    /// Reads a scene written by `to_text`. Trees have to be of one of the species and lack no
+    /// more derivation steps than placed trees may.
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut species: Vec<SpeciesConfig> = Vec::new();
+        let mut layout = TreeLayout::default();
+        let mut section = Section::Start;
+        for (line_index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("Line {}: {message}", line_index + 1);
+
+            match line {
+                "[species]" => {
+                    let mut config = SpeciesConfig::default();
+                    config.lsystem_config.production_rules.clear();
+                    config.lsystem_config.terminal_ages.clear();
+                    species.push(config);
+                    section = Section::Species;
+                    continue;
+                }
+                "[trees]" => {
+                    section = Section::Trees;
+                    continue;
+                }
+                _ => {}
+            }
+            match section {
+                Section::Start => return Err(error("expected [species] or [trees]")),
+                Section::Species => {
+                    let (key, value) = line
+                        .split_once('=')
+                        .ok_or_else(|| error("expected a key and a value separated by '='"))?;
+                    let config = species.last_mut().unwrap();
+                    Self::read_species_value(config, key.trim(), value.trim())
+                        .map_err(|e| error(&e))?;
+                }
+                Section::Trees => layout.push_line(line).map_err(|e| error(&e))?,
+            }
+        }
+
+        if species.is_empty() {
+            return Err("The scene has no species".to_string());
+        }
+        layout.validate(species.len())?;
+        Ok(Self { species, layout })
+    }
+
+    fn read_species_value(
+        config: &mut SpeciesConfig,
+        key: &str,
+        value: &str,
+    ) -> Result<(), String> {
+        let lsystem_config = &mut config.lsystem_config;
+        match key {
+            "name" => config.name = value.to_string(),
+            "preset" => {
+                config.preset_selection = PRESETS
+                    .into_iter()
+                    .find(|preset| format!("{preset:?}") == value)
+                    .ok_or_else(|| format!("unknown preset '{value}'"))?;
+            }
+            "model" => {
+                config.model_selection = MODELS
+                    .into_iter()
+                    .find(|model| format!("{model:?}") == value)
+                    .ok_or_else(|| format!("unknown model '{value}'"))?;
+            }
+            "axiom" => lsystem_config.axiom = value.to_string(),
+            "rule" => {
+                let (predecessor, successor) = value
+                    .split_once("->")
+                    .ok_or("expected a rule of the form 'predecessor -> successor'")?;
+                lsystem_config
+                    .production_rules
+                    .push((predecessor.trim().to_string(), successor.trim().to_string()));
+            }
+            "iterations" => {
+                lsystem_config.n_iterations = value
+                    .parse()
+                    .map_err(|e| format!("invalid iterations: {e}"))?;
+            }
+            "angle" => {
+                lsystem_config.angle = value.parse().map_err(|e| format!("invalid angle: {e}"))?;
+            }
+            "height" => {
+                lsystem_config.fractal_height =
+                    value.parse().map_err(|e| format!("invalid height: {e}"))?;
+            }
+            "terminal_age" => {
+                let (symbol, terminal_age) = value
+                    .rsplit_once(char::is_whitespace)
+                    .ok_or("expected a symbol and its terminal age")?;
+                let terminal_age = terminal_age
+                    .parse()
+                    .map_err(|e| format!("invalid terminal age: {e}"))?;
+                lsystem_config
+                    .terminal_ages
+                    .push((symbol.trim().to_string(), terminal_age));
+            }
+            "color_low" => config.interpolation_color_low = Self::read_color(value)?,
+            "color_high" => config.interpolation_color_high = Self::read_color(value)?,
+            "weight" => {
+                config.weight = value.parse().map_err(|e| format!("invalid weight: {e}"))?;
+            }
+            _ => return Err(format!("unknown key '{key}'")),
+        }
+        Ok(())
+    }
+
+    fn read_color(value: &str) -> Result<[f32; 3], String> {
+        let components = value
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|e| format!("invalid color: {e}"))?;
+        components
+            .try_into()
+            .map_err(|_| "expected three color components".to_string())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        if let Some(directory) = Path::new(path).parent() {
+            std::fs::create_dir_all(directory)
+                .map_err(|e| format!("Failed to create {}: {e}", directory.display()))?;
+        }
+        std::fs::write(path, self.to_text()).map_err(|e| format!("Failed to save {path}: {e}"))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to load {path}: {e}"))?;
+        Self::from_text(&text).map_err(|e| format!("Failed to load {path}: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scene_round_trip() {
+        let mut bush = SpeciesConfig {
+            name: "Bush with {Apex}".to_string(),
+            lsystem_config: PresetSelection::TrunkTree.to_config(),
+            preset_selection: PresetSelection::TrunkTree,
+            model_selection: ModelSelection::LeafCard,
+            weight: 0.25,
+            ..SpeciesConfig::default()
+        };
+        bush.lsystem_config
+            .terminal_ages
+            .push(("{Apex}".to_string(), 1.5));
+        let scene = SceneFile {
+            species: vec![SpeciesConfig::default(), bush],
+            layout: TreeLayout {
+                displacement_matrices: vec![glm::translation(&glm::vec3(1.5, -0.25, 3.0))],
+                tree_species: vec![1],
+                iteration_offsets: vec![1],
+            },
+        };
+        assert_eq!(SceneFile::from_text(&scene.to_text()), Ok(scene));
+    }
+
+    #[test]
+    fn test_invalid_scene() {
+        let error = SceneFile::from_text("[species]\nname = A\ncolour = 1 2 3\n").unwrap_err();
+        assert!(error.starts_with("Line 3:"));
+        assert!(SceneFile::from_text("[trees]\n").is_err());
+
+        // Trees of species that are not in the scene
+        let tree = "0 0 1 0 0 0 0 1 0 0 0 0 1 0 0 0 0 1";
+        assert!(SceneFile::from_text(&format!("[species]\n[trees]\n{tree}")).is_ok());
+        let error = SceneFile::from_text(&format!("[species]\n[trees]\n1{tree}")).unwrap_err();
+        assert!(error.starts_with("Tree 0"));
+    }
+}
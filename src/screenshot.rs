@@ -0,0 +1,176 @@
+use glm::Mat4;
+use image::RgbaImage;
+use std::path::{Path, PathBuf};
+
+/// Largest side of the offscreen target a high-resolution screenshot is rendered in
+pub const TILE_SIZE: u32 = 2048;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScreenshotSettings {
+    /// Directory screenshots are saved to, with increasing numbers
+    pub directory: String,
+    /// Whether window screenshots show the control panel and overlays
+    pub include_gui: bool,
+    /// Size of high-resolution screenshots in pixels
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for ScreenshotSettings {
+    fn default() -> Self {
+        Self {
+            directory: "screenshots".to_string(),
+            include_gui: false,
+            width: 7680,
+            height: 4320,
+        }
+    }
+}
+
+/// Part of an image rendered at once, in pixels from the top left corner
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Splits an image into tiles no larger than `tile_size`, row by row from the top
+pub fn tiles(width: u32, height: u32, tile_size: u32) -> Vec<Tile> {
+    (0..height)
+        .step_by(tile_size as usize)
+        .flat_map(|y| {
+            (0..width).step_by(tile_size as usize).map(move |x| Tile {
+                x,
+                y,
+                width: tile_size.min(width - x),
+                height: tile_size.min(height - y),
+            })
+        })
+        .collect()
+}
+
+/// Narrows the projection of the whole image to the tile, so that drawing with it fills the
+/// tile's viewport with its part of the image
+#[allow(clippy::cast_precision_loss)]
+pub fn tile_projection(projection: &Mat4, tile: &Tile, width: u32, height: u32) -> Mat4 {
+    let (width, height) = (width as f32, height as f32);
+    // Bounds of the tile in normalized device coordinates, where Y points up
+    let left = 2.0 * tile.x as f32 / width - 1.0;
+    let right = 2.0 * (tile.x + tile.width) as f32 / width - 1.0;
+    let top = 1.0 - 2.0 * tile.y as f32 / height;
+    let bottom = 1.0 - 2.0 * (tile.y + tile.height) as f32 / height;
+
+    let mut crop = Mat4::identity();
+    crop[(0, 0)] = 2.0 / (right - left);
+    crop[(0, 3)] = -(right + left) / (right - left);
+    crop[(1, 1)] = 2.0 / (top - bottom);
+    crop[(1, 3)] = -(top + bottom) / (top - bottom);
+    crop * projection
+}
+
+/// Copies RGBA pixels read back from OpenGL, whose rows go from the bottom up, into the tile
+/// of the image. `row_width` is the width of the read back rows, which may exceed the tile's.
+pub fn copy_tile(image: &mut RgbaImage, pixels: &[u8], row_width: u32, tile: &Tile) {
+    for row in 0..tile.height {
+        let start = (row * row_width) as usize * 4;
+        let source = &pixels[start..start + tile.width as usize * 4];
+        let y = tile.y + tile.height - 1 - row;
+        for (column, pixel) in source.chunks_exact(4).enumerate() {
+            let x = tile.x + u32::try_from(column).unwrap_or(u32::MAX);
+            image.put_pixel(x, y, image::Rgba([pixel[0], pixel[1], pixel[2], pixel[3]]));
+        }
+    }
+}
+
+/// Puts two images of the same height next to each other
+pub fn side_by_side(left: &RgbaImage, right: &RgbaImage) -> RgbaImage {
+    let mut image = RgbaImage::new(left.width() + right.width(), left.height());
+    image::imageops::replace(&mut image, left, 0, 0);
+    image::imageops::replace(&mut image, right, i64::from(left.width()), 0);
+    image
+}
+
+/// Returns the first path of the form `prefix_0001.png` in the directory that is not taken
+pub fn next_free_path(directory: &Path, prefix: &str) -> PathBuf {
+    (1..=u32::MAX)
+        .map(|number| directory.join(format!("{prefix}_{number:04}.png")))
+        .find(|path| !path.exists())
+        .expect("Ran out of screenshot numbers")
+}
+
+/// Saves the image as PNG, creating the directory if it does not exist
+pub fn save_png(image: &RgbaImage, path: &Path) -> Result<(), String> {
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)
+            .map_err(|e| format!("Failed to create {}: {e}", directory.display()))?;
+    }
+    image
+        .save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to save {}: {e}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tiles_cover_image() {
+        let tiles = tiles(5000, 3000, 2048);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(
+            tiles[2],
+            Tile {
+                x: 4096,
+                y: 0,
+                width: 904,
+                height: 2048
+            }
+        );
+        let area: u32 = tiles.iter().map(|tile| tile.width * tile.height).sum();
+        assert_eq!(area, 5000 * 3000);
+    }
+
+    #[test]
+    fn test_tile_projection() {
+        let projection = glm::perspective_rh_zo(2.0, 45_f32.to_radians(), 0.1, 100.0);
+        // Top right quarter of the image
+        let tile = Tile {
+            x: 100,
+            y: 0,
+            width: 100,
+            height: 50,
+        };
+        let cropped = tile_projection(&projection, &tile, 200, 100);
+
+        // A point projected to the top right of the image stays at the top right of the tile,
+        // the center of the image moves to the bottom left of the tile
+        for (ndc, expected) in [((1.0, 1.0), (1.0, 1.0)), ((0.0, 0.0), (-1.0, -1.0))] {
+            let clip = glm::vec4(ndc.0, ndc.1, 0.5, 1.0);
+            let point = glm::inverse(&projection) * clip;
+            let projected = cropped * point;
+            let projected = projected.xy() / projected.w;
+            assert!((projected.x - expected.0).abs() < 1e-4);
+            assert!((projected.y - expected.1).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_copy_tile_flips_rows() {
+        // Two rows of three pixels read back from the bottom up, the tile uses two columns
+        let pixels: Vec<u8> = (0..6).flat_map(|i| [i, 0, 0, 255]).collect();
+        let mut image = RgbaImage::new(4, 2);
+        let tile = Tile {
+            x: 2,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        copy_tile(&mut image, &pixels, 3, &tile);
+        assert_eq!(image.get_pixel(2, 1).0, [0, 0, 0, 255]);
+        assert_eq!(image.get_pixel(3, 1).0, [1, 0, 0, 255]);
+        assert_eq!(image.get_pixel(2, 0).0, [3, 0, 0, 255]);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0]);
+    }
+}
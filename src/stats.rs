@@ -0,0 +1,95 @@
+use crate::culling::Aabb;
+use crate::scene::Species;
+use glm::Vec3;
+
+/// Size of a generated plant, shown in the statistics panel
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlantStats {
+    /// Number of symbols of the derivation the plant was drawn from
+    pub derivation_length: u64,
+    pub segments: usize,
+    /// Points from which more than one segment continues
+    pub branch_points: usize,
+    /// Greatest number of brackets enclosing a segment
+    pub max_branch_depth: u32,
+    /// Sum of the lengths of all segments, in the plant's local space
+    pub total_branch_length: f32,
+    /// Size of the box around the plant in its local space
+    pub dimensions: Vec3,
+    /// Scale applied by `Species` to fit the plant to its target height
+    pub scale_factor: f32,
+    /// Triangles sent to the GPU for a single tree
+    pub triangles: usize,
+}
+
+impl PlantStats {
+    /// Computes the statistics of the most detailed level of the species
+    pub fn new(species: &Species, derivation_length: u64) -> Self {
+        let segments = species.segments(0);
+        let model = &species.fractal_base().geometry;
+
+        // Children of every segment, with those starting at the root counted last
+        let mut children = vec![0_usize; segments.len() + 1];
+        for segment in segments {
+            children[segment.parent.unwrap_or(segments.len())] += 1;
+        }
+
+        let model_bounds = Aabb::from_model(model);
+        let mut bounds = Aabb::empty();
+        for segment in segments {
+            bounds.union(&model_bounds.transformed(&segment.transformation));
+        }
+
+        Self {
+            derivation_length,
+            segments: segments.len(),
+            branch_points: children.iter().filter(|&&count| count > 1).count(),
+            max_branch_depth: segments
+                .iter()
+                .map(|segment| segment.branch_order)
+                .max()
+                .unwrap_or(0),
+            // The turtle stretches the unit Y axis of every segment to its length
+            total_branch_length: segments
+                .iter()
+                .map(|segment| segment.transformation.column(1).xyz().norm())
+                .sum(),
+            dimensions: if bounds.is_empty() {
+                Vec3::zeros()
+            } else {
+                bounds.max - bounds.min
+            },
+            scale_factor: species.scale_factor(),
+            triangles: segments.len() * species.fractal_base().triangle_count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::ModelSelection;
+    use crate::model_loader::load_model;
+    use crate::symbol::Alphabet;
+    use crate::turtle::TurtleInterpreter;
+
+    #[test]
+    fn test_plant_stats() {
+        let mut alphabet = Alphabet::default();
+        let symbols = alphabet.parse("F[+F]F[-F[+F]F]");
+        let segments = TurtleInterpreter::interpret(symbols, &alphabet, 30.0);
+        let model = load_model(ModelSelection::Cylinder);
+        let triangles_per_segment = model.triangle_count();
+        let species = Species::new(model, vec![segments], 0, 4.0, ([0.0; 3], [1.0; 3]));
+
+        let stats = PlantStats::new(&species, 15);
+        assert_eq!(stats.derivation_length, 15);
+        assert_eq!(stats.segments, 6);
+        assert_eq!(stats.branch_points, 2);
+        assert_eq!(stats.max_branch_depth, 2);
+        assert_eq!(stats.triangles, 6 * triangles_per_segment);
+        assert!((stats.total_branch_length - 6.0 * stats.scale_factor).abs() < 1e-4);
+        // Tilted branches stick out above the tip the plant is fitted to the height with
+        assert!(stats.dimensions.y >= 4.0 - 1e-4);
+    }
+}
@@ -0,0 +1,162 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Index of a symbol in its `Alphabet`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SymbolId(u16);
+
+impl SymbolId {
+    pub fn index(self) -> usize {
+        usize::from(self.0)
+    }
+}
+
+/// Names of the symbols of an L-system, each stored once and referred to by its id
+#[derive(Debug, Clone, Default)]
+pub struct Alphabet {
+    names: Vec<String>,
+    ids: HashMap<String, SymbolId>,
+}
+
+impl Alphabet {
+    /// Returns the id of the symbol, adding it to the alphabet if it is new
+    pub fn intern(&mut self, name: &str) -> SymbolId {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+        let id = SymbolId(
+            u16::try_from(self.names.len()).expect("alphabet has more than 65536 symbols"),
+        );
+        self.names.push(name.to_string());
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    pub fn get(&self, name: &str) -> Option<SymbolId> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: SymbolId) -> &str {
+        &self.names[id.index()]
+    }
+
+    /// Name of the symbol, `None` for an id from a larger alphabet
+    pub fn get_name(&self, id: SymbolId) -> Option<&str> {
+        self.names.get(id.index()).map(String::as_str)
+    }
+
+    /// Names of all symbols, in the order of their ids
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Splits the text into symbols with `tokenize` and interns them
+    pub fn parse(&mut self, text: &str) -> Vec<SymbolId> {
+        tokenize(text)
+            .into_iter()
+            .map(|(_, name)| self.intern(name))
+            .collect()
+    }
+
+    /// Interns the single symbol written as `text`, see `name_of`
+    pub fn parse_symbol(&mut self, text: &str) -> SymbolId {
+        self.intern(name_of(text))
+    }
+}
+
+/// Splits text into symbol names, each with the position of its first character. Every
+/// character is a symbol on its own, names of several characters are written in braces
+/// (`{Apex}`, `{Leaf2}`). An unclosed brace makes the rest of the text a single name that
+/// starts with the brace.
+pub fn tokenize(text: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().enumerate();
+    while let Some((position, (start, first))) = chars.next() {
+        if first != '{' {
+            tokens.push((position, &text[start..start + first.len_utf8()]));
+            continue;
+        }
+        let name_start = start + first.len_utf8();
+        let Some(name_length) = text[name_start..].find('}') else {
+            tokens.push((position, &text[start..]));
+            break;
+        };
+        let name = &text[name_start..name_start + name_length];
+        tokens.push((position, name));
+        // Skip the name and the closing brace
+        chars.nth(name.chars().count());
+    }
+    tokens
+}
+
+/// Name of the single symbol written as `text`, which is the text itself unless it is a name
+/// in braces
+pub fn name_of(text: &str) -> &str {
+    text.strip_prefix('{')
+        .and_then(|text| text.strip_suffix('}'))
+        .unwrap_or(text)
+}
+
+/// Writes the symbol name the way `tokenize` reads it back, in braces if it is longer than one
+/// character
+pub fn written(name: &str) -> Cow<'_, str> {
+    if name.chars().count() > 1 {
+        Cow::Owned(format!("{{{name}}}"))
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokenize("FX[+{Apex}]{Leaf2}Fa"),
+            [
+                (0, "F"),
+                (1, "X"),
+                (2, "["),
+                (3, "+"),
+                (4, "Apex"),
+                (10, "]"),
+                (11, "Leaf2"),
+                (18, "F"),
+                (19, "a"),
+            ]
+        );
+        assert_eq!(tokenize("a\\F"), [(0, "a"), (1, "\\"), (2, "F")]);
+        assert_eq!(tokenize("F{}{Ap"), [(0, "F"), (1, ""), (3, "{Ap")]);
+        assert!(tokenize("").is_empty());
+    }
+
+    #[test]
+    fn test_written_names_read_back() {
+        for name in ["F", "+", "Apex", "Leaf2"] {
+            assert_eq!(tokenize(&written(name)), [(0, name)]);
+            assert_eq!(name_of(&written(name)), name);
+        }
+    }
+
+    #[test]
+    fn test_alphabet() {
+        let mut alphabet = Alphabet::default();
+        let symbols = alphabet.parse("{Apex}[+F]{Apex}");
+        assert_eq!(alphabet.len(), 5);
+        assert_eq!(symbols[0], symbols[5]);
+        assert_eq!(alphabet.get("F"), Some(symbols[3]));
+        assert_eq!(alphabet.get("A"), None);
+        assert_eq!(alphabet.get_name(symbols[3]), Some("F"));
+        assert_eq!(Alphabet::default().get_name(symbols[3]), None);
+        assert_eq!(
+            alphabet.names().collect::<Vec<_>>(),
+            ["Apex", "[", "+", "F", "]"]
+        );
+    }
+}
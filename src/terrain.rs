@@ -0,0 +1,332 @@
+use crate::model_loader::{self, Model3D};
+use std::f32::consts::TAU;
+use tobj::{Material, Mesh, Model};
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum TerrainSource {
+    #[default]
+    Flat,
+    Noise,
+    Heightmap,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TerrainConfig {
+    pub source: TerrainSource,
+    /// Terrain spans from -`half_size` to `half_size` along both X and Z
+    pub half_size: f32,
+    /// Number of grid cells along each side of the terrain mesh
+    pub resolution: u32,
+    pub max_height: f32,
+    pub seed: u32,
+    /// Number of noise features per world unit of the first octave
+    pub noise_frequency: f32,
+    pub noise_octaves: u32,
+    /// Path of a grayscale image, black is mapped to height 0 and white to `max_height`
+    pub heightmap_path: String,
+}
+
+impl Default for TerrainConfig {
+    fn default() -> Self {
+        Self {
+            source: TerrainSource::Flat,
+            half_size: 100.0,
+            resolution: 128,
+            max_height: 4.0,
+            seed: 0,
+            noise_frequency: 0.03,
+            noise_octaves: 5,
+            heightmap_path: "assets/heightmaps/hills.png".to_string(),
+        }
+    }
+}
+
+/// Regular grid of height samples covering a square area centered at the origin
+#[derive(Debug, Clone)]
+pub struct Terrain {
+    /// Row-major samples, `(resolution + 1)^2` of them
+    heights: Vec<f32>,
+    resolution: usize,
+    half_size: f32,
+}
+
+impl Terrain {
+    pub fn flat(half_size: f32) -> Self {
+        Self {
+            heights: vec![0.0; 4],
+            resolution: 1,
+            half_size,
+        }
+    }
+
+    /// Builds the terrain described by the config, errors only when loading the heightmap fails
+    pub fn from_config(config: &TerrainConfig) -> Result<Self, String> {
+        match config.source {
+            TerrainSource::Flat => Ok(Self::flat(config.half_size)),
+            TerrainSource::Noise => Ok(Self::from_noise(config)),
+            TerrainSource::Heightmap => Self::from_heightmap(config),
+        }
+    }
+
+    /// Generates terrain from fractal Brownian motion of gradient noise
+    #[allow(clippy::cast_precision_loss)]
+    pub fn from_noise(config: &TerrainConfig) -> Self {
+        let resolution = config.resolution.max(1) as usize;
+        Self::from_samples(resolution, config.half_size, |u, v| {
+            let x = (u * 2.0 - 1.0) * config.half_size * config.noise_frequency;
+            let z = (v * 2.0 - 1.0) * config.half_size * config.noise_frequency;
+            fbm(x, z, config.noise_octaves, config.seed) * config.max_height
+        })
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    pub fn from_heightmap(config: &TerrainConfig) -> Result<Self, String> {
+        let image = image::open(&config.heightmap_path)
+            .map_err(|e| format!("Failed to load heightmap {}: {e}", config.heightmap_path))?
+            .to_luma32f();
+        let (width, height) = image.dimensions();
+
+        let resolution = config.resolution.max(1) as usize;
+        Ok(Self::from_samples(resolution, config.half_size, |u, v| {
+            let pixel_x = u * (width - 1) as f32;
+            let pixel_y = v * (height - 1) as f32;
+            let sample = bilinear(pixel_x, pixel_y, |x, y| {
+                let x = x.min(width as usize - 1);
+                let y = y.min(height as usize - 1);
+                #[allow(clippy::cast_possible_truncation)]
+                image.get_pixel(x as u32, y as u32).0[0]
+            });
+            sample * config.max_height
+        }))
+    }
+
+    /// Samples `height(u, v)` on the grid, with `u` and `v` going from 0 to 1 across the terrain
+    #[allow(clippy::cast_precision_loss)]
+    fn from_samples(resolution: usize, half_size: f32, height: impl Fn(f32, f32) -> f32) -> Self {
+        let heights = (0..=resolution)
+            .flat_map(|row| (0..=resolution).map(move |column| (row, column)))
+            .map(|(row, column)| {
+                height(
+                    column as f32 / resolution as f32,
+                    row as f32 / resolution as f32,
+                )
+            })
+            .collect();
+
+        Self {
+            heights,
+            resolution,
+            half_size,
+        }
+    }
+
+    fn sample(&self, column: usize, row: usize) -> f32 {
+        let column = column.min(self.resolution);
+        let row = row.min(self.resolution);
+        self.heights[row * (self.resolution + 1) + column]
+    }
+
+    /// Returns the interpolated terrain height at the given world position.
+    /// Positions outside of the terrain use the height of the nearest edge.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let cell_size = self.cell_size();
+        let grid_x = ((x + self.half_size) / cell_size).clamp(0.0, self.resolution as f32);
+        let grid_z = ((z + self.half_size) / cell_size).clamp(0.0, self.resolution as f32);
+        bilinear(grid_x, grid_z, |column, row| self.sample(column, row))
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn cell_size(&self) -> f32 {
+        2.0 * self.half_size / self.resolution as f32
+    }
+
+    /// Builds a triangle mesh of the terrain in world space
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    pub fn to_model(&self, material: Material) -> Model3D {
+        let cell_size = self.cell_size();
+        let vertices_per_row = self.resolution + 1;
+
+        let mut positions = Vec::with_capacity(vertices_per_row * vertices_per_row * 3);
+        let mut normals = Vec::with_capacity(vertices_per_row * vertices_per_row * 3);
+        for row in 0..=self.resolution {
+            for column in 0..=self.resolution {
+                positions.extend([
+                    column as f32 * cell_size - self.half_size,
+                    self.sample(column, row),
+                    row as f32 * cell_size - self.half_size,
+                ]);
+
+                // Central differences, one-sided at the edges
+                let dx = self.sample(column + 1, row) - self.sample(column.saturating_sub(1), row);
+                let dz = self.sample(column, row + 1) - self.sample(column, row.saturating_sub(1));
+                let normal = glm::normalize(&glm::vec3(-dx, 2.0 * cell_size, -dz));
+                normals.extend([normal.x, normal.y, normal.z]);
+            }
+        }
+
+        let mut indices = Vec::with_capacity(self.resolution * self.resolution * 6);
+        for row in 0..self.resolution {
+            for column in 0..self.resolution {
+                let top_left = (row * vertices_per_row + column) as u32;
+                let top_right = top_left + 1;
+                let bottom_left = top_left + vertices_per_row as u32;
+                let bottom_right = bottom_left + 1;
+                indices.extend([
+                    top_left,
+                    bottom_left,
+                    top_right,
+                    top_right,
+                    bottom_left,
+                    bottom_right,
+                ]);
+            }
+        }
+
+        let mesh = Mesh {
+            positions,
+            normals,
+            indices,
+            ..Mesh::default()
+        };
+
+        Model3D {
+            tangents: model_loader::tangents(&mesh),
+            geometry: Model::new(mesh, "terrain".to_string()),
+            material,
+            diffuse_texture: None,
+            normal_texture: None,
+            foliage: false,
+        }
+    }
+}
+
+/// Bilinearly interpolates values of a grid at a fractional position
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn bilinear(x: f32, y: f32, value: impl Fn(usize, usize) -> f32) -> f32 {
+    let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+    let (tx, ty) = (x.fract(), y.fract());
+
+    let top = value(x0, y0) * (1.0 - tx) + value(x0 + 1, y0) * tx;
+    let bottom = value(x0, y0 + 1) * (1.0 - tx) + value(x0 + 1, y0 + 1) * tx;
+    top * (1.0 - ty) + bottom * ty
+}
+
+/// Integer hash used to pick pseudo-random gradients for lattice points
+#[allow(clippy::cast_sign_loss)]
+fn hash(x: i32, y: i32, seed: u32) -> u32 {
+    let mut h = (x as u32).wrapping_mul(0x27d4_eb2d)
+        ^ (y as u32).wrapping_mul(0x1656_67b1)
+        ^ seed.wrapping_mul(0x9e37_79b9);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^ (h >> 16)
+}
+
+/// Two-dimensional gradient (Perlin) noise, roughly in the range -0.7..0.7
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn perlin(x: f32, y: f32, seed: u32) -> f32 {
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let (fx, fy) = (x - x.floor(), y - y.floor());
+
+    let dot_gradient = |ix: i32, iy: i32, dx: f32, dy: f32| {
+        let angle = hash(ix, iy, seed) as f32 / u32::MAX as f32 * TAU;
+        angle.cos() * dx + angle.sin() * dy
+    };
+    let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+
+    let (u, v) = (fade(fx), fade(fy));
+    let top = dot_gradient(x0, y0, fx, fy) * (1.0 - u) + dot_gradient(x0 + 1, y0, fx - 1.0, fy) * u;
+    let bottom = dot_gradient(x0, y0 + 1, fx, fy - 1.0) * (1.0 - u)
+        + dot_gradient(x0 + 1, y0 + 1, fx - 1.0, fy - 1.0) * u;
+    top * (1.0 - v) + bottom * v
+}
+
+/// Fractal Brownian motion - sum of noise octaves of doubling frequency and halving amplitude,
+/// normalized to the range 0..1
+fn fbm(x: f32, y: f32, octaves: u32, seed: u32) -> f32 {
+    let mut value = 0.0;
+    let mut amplitude = 1.0;
+    let mut frequency = 1.0;
+    let mut total_amplitude = 0.0;
+
+    for octave in 0..octaves.max(1) {
+        value += perlin(x * frequency, y * frequency, seed.wrapping_add(octave)) * amplitude;
+        total_amplitude += amplitude;
+        amplitude *= 0.5;
+        frequency *= 2.0;
+    }
+
+    (value / total_amplitude / 1.4 + 0.5).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flat_terrain_has_zero_height() {
+        let terrain = Terrain::flat(10.0);
+        assert!(terrain.height_at(0.0, 0.0).abs() < f32::EPSILON);
+        assert!(terrain.height_at(25.0, -25.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_height_at_interpolates_samples() {
+        let terrain = Terrain::from_samples(2, 1.0, |u, _| u);
+        assert!((terrain.height_at(-1.0, 0.0) - 0.0).abs() < 1e-6);
+        assert!((terrain.height_at(0.0, 0.3) - 0.5).abs() < 1e-6);
+        assert!((terrain.height_at(0.5, 0.0) - 0.75).abs() < 1e-6);
+        assert!((terrain.height_at(5.0, 0.0) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_noise_terrain_is_deterministic_and_bounded() {
+        let config = TerrainConfig {
+            source: TerrainSource::Noise,
+            resolution: 16,
+            ..TerrainConfig::default()
+        };
+        let first = Terrain::from_noise(&config);
+        let second = Terrain::from_noise(&config);
+        assert_eq!(first.heights, second.heights);
+        assert!(
+            first
+                .heights
+                .iter()
+                .all(|h| (0.0..=config.max_height).contains(h))
+        );
+
+        let reseeded = Terrain::from_noise(&TerrainConfig { seed: 1, ..config });
+        assert_ne!(first.heights, reseeded.heights);
+    }
+
+    #[test]
+    fn test_heightmap_loads() {
+        let config = TerrainConfig {
+            source: TerrainSource::Heightmap,
+            resolution: 32,
+            ..TerrainConfig::default()
+        };
+        let terrain = Terrain::from_config(&config).unwrap();
+        assert!(terrain.heights.iter().any(|h| *h > 0.0));
+
+        let missing = TerrainConfig {
+            heightmap_path: "assets/heightmaps/missing.png".to_string(),
+            ..config
+        };
+        assert!(Terrain::from_config(&missing).is_err());
+    }
+
+    #[test]
+    fn test_terrain_mesh() {
+        let terrain = Terrain::from_samples(4, 1.0, |u, v| u * v);
+        let model = terrain.to_model(Material::default());
+        let mesh = &model.geometry.mesh;
+        assert_eq!(mesh.positions.len(), 25 * 3);
+        assert_eq!(mesh.positions.len(), mesh.normals.len());
+        assert_eq!(mesh.indices.len(), 16 * 6);
+    }
+}
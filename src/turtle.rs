@@ -1,19 +1,49 @@
+use crate::symbol::{Alphabet, SymbolId};
 use glm::Mat4;
 
 pub struct TurtleInterpreter {}
 
+/// A single segment drawn by the turtle
+#[derive(Clone, Debug)]
+pub struct Segment {
+    pub transformation: Mat4,
+    /// Number of branchings between the trunk and the segment
+    pub branch_order: u32,
+    /// Number of segments between the root of the plant and the segment
+    pub depth: u32,
+    /// Symbol that drew the segment
+    pub symbol: SymbolId,
+    /// Position of the symbol in the interpreted derivation
+    pub symbol_index: usize,
+    /// Index of the segment this one continues from, `None` if it starts at the root
+    pub parent: Option<usize>,
+}
+
 #[derive(Clone, Debug)]
 struct TurtleState {
     position: glm::Vec3,
     direction: glm::Vec3,
+    /// Number of segments drawn on the way from the root to the current position
+    depth: u32,
+    /// Index of the segment ending at the current position
+    last_segment: Option<usize>,
 }
 
 impl TurtleInterpreter {
-    fn parse_input(input: &str) -> Vec<TurtleCommand> {
+    /// Returns the drawing commands with their symbol, length and position in the input, parsed
+    /// as the input is consumed by looking up each symbol in a table built from the alphabet
+    fn parse_input(
+        input: impl IntoIterator<Item = (SymbolId, f32)>,
+        alphabet: &Alphabet,
+    ) -> impl Iterator<Item = (TurtleCommand, SymbolId, f32, usize)> {
+        let commands: Vec<Option<TurtleCommand>> =
+            alphabet.names().map(TurtleCommand::from_name).collect();
         input
-            .chars()
-            .filter_map(|c| TurtleCommand::try_from(c).ok())
-            .collect()
+            .into_iter()
+            .enumerate()
+            .filter_map(move |(i, (symbol, length))| {
+                commands[symbol.index()].map(|command| (command, symbol, length, i))
+            })
     }
 
     fn get_rotation_matrix(axis: Axis, angles: f32) -> Mat4 {
@@ -35,26 +65,58 @@ impl TurtleInterpreter {
             ))
         .xyz();
         TurtleState {
-            position: current_state.position,
             direction: glm::normalize(&new_direction),
+            ..current_state.clone()
         }
     }
 
-    pub fn interpret(lsystem: &str, angle: f32) -> Vec<Mat4> {
-        let commands = Self::parse_input(lsystem);
+    /// Returns true if the symbol draws a segment
+    pub fn is_segment(name: &str) -> bool {
+        matches!(
+            TurtleCommand::from_name(name),
+            Some(TurtleCommand::MoveForward)
+        )
+    }
+
+    /// Returns true if the symbol is drawn or moves the turtle
+    pub fn is_command(name: &str) -> bool {
+        TurtleCommand::from_name(name).is_some()
+    }
+
+    /// Interprets symbols as they are produced, e.g. streamed from `LSystem::derive`
+    pub fn interpret(
+        symbols: impl IntoIterator<Item = SymbolId>,
+        alphabet: &Alphabet,
+        angle: f32,
+    ) -> Vec<Segment> {
+        Self::interpret_scaled(
+            symbols.into_iter().map(|symbol| (symbol, 1.0)),
+            alphabet,
+            angle,
+        )
+    }
 
-        let mut transformations: Vec<Mat4> = Vec::new();
+    /// Interprets symbols paired with the length of the segment they draw.
+    /// The length only affects `F`, whose segment is stretched along its direction.
+    pub fn interpret_scaled(
+        symbols: impl IntoIterator<Item = (SymbolId, f32)>,
+        alphabet: &Alphabet,
+        angle: f32,
+    ) -> Vec<Segment> {
+        let mut segments: Vec<Segment> = Vec::new();
         let mut state_stack: Vec<TurtleState> = Vec::new();
 
         let mut current_state = TurtleState {
             position: glm::vec3(0.0, 0.0, 0.0),
             direction: glm::vec3(0.0, 1.0, 0.0), // pointing up (Y+)
+            depth: 0,
+            last_segment: None,
         };
 
-        for command in &commands {
+        for (command, symbol, length, symbol_index) in Self::parse_input(symbols, alphabet) {
             match command {
                 TurtleCommand::MoveForward => {
-                    let new_position = current_state.position + current_state.direction;
+                    let new_position = current_state.position + current_state.direction * length;
 
                     // Translation to midpoint
                     let translation = glm::translation(&current_state.position);
@@ -65,8 +127,20 @@ impl TurtleInterpreter {
                     let angle = up.dot(&current_state.direction).acos();
                     let rotation = glm::rotation(angle, &glm::normalize(&axis));
 
-                    transformations.push(translation * rotation);
+                    // Stretch the base model along its local Y axis
+                    let scale = glm::scaling(&glm::vec3(1.0, length, 1.0));
+
+                    segments.push(Segment {
+                        transformation: translation * rotation * scale,
+                        branch_order: u32::try_from(state_stack.len()).unwrap_or(u32::MAX),
+                        depth: current_state.depth,
+                        symbol,
+                        symbol_index,
+                        parent: current_state.last_segment,
+                    });
                     current_state.position = new_position;
+                    current_state.depth += 1;
+                    current_state.last_segment = Some(segments.len() - 1);
                 }
                 TurtleCommand::RotateLeft => {
                     current_state = Self::handle_rotation(
@@ -115,10 +189,11 @@ impl TurtleInterpreter {
             }
         }
 
-        transformations
+        segments
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum TurtleCommand {
     MoveForward,
     RotateLeft,
@@ -131,22 +206,25 @@ enum TurtleCommand {
     PopState,
 }
 
-impl TryFrom<char> for TurtleCommand {
-    type Error = String;
-
-    fn try_from(value: char) -> Result<Self, Self::Error> {
-        match value {
-            'F' => Ok(TurtleCommand::MoveForward),
-            '+' => Ok(TurtleCommand::RotateLeft),
-            '-' => Ok(TurtleCommand::RotateRight),
-            '&' => Ok(TurtleCommand::PitchDown),
-            '^' => Ok(TurtleCommand::PitchUp),
-            '\\' => Ok(TurtleCommand::RollLeft),
-            '/' => Ok(TurtleCommand::RollRight),
-            '[' => Ok(TurtleCommand::PushState),
-            ']' => Ok(TurtleCommand::PopState),
-            _ => Err(format!("Ignoring non-drawing symbol: {value}")),
-        }
+/// Symbols understood by the turtle, any other symbol is ignored
+const COMMANDS: [(&str, TurtleCommand); 9] = [
+    ("F", TurtleCommand::MoveForward),
+    ("+", TurtleCommand::RotateLeft),
+    ("-", TurtleCommand::RotateRight),
+    ("&", TurtleCommand::PitchDown),
+    ("^", TurtleCommand::PitchUp),
+    ("\\", TurtleCommand::RollLeft),
+    ("/", TurtleCommand::RollRight),
+    ("[", TurtleCommand::PushState),
+    ("]", TurtleCommand::PopState),
+];
+
+impl TurtleCommand {
+    fn from_name(name: &str) -> Option<Self> {
+        COMMANDS
+            .iter()
+            .find(|(command_name, _)| *command_name == name)
+            .map(|&(_, command)| command)
     }
 }
 
@@ -156,3 +234,24 @@ enum Axis {
     Y,
     Z,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segment_origin() {
+        let mut alphabet = Alphabet::default();
+        let symbols = alphabet.parse("F{Apex}[+F]F");
+        let segments = TurtleInterpreter::interpret(symbols, &alphabet, 25.0);
+        let summary: Vec<(u32, u32, usize, Option<usize>)> = segments
+            .iter()
+            .map(|s| (s.depth, s.branch_order, s.symbol_index, s.parent))
+            .collect();
+        assert_eq!(
+            summary,
+            [(0, 0, 0, None), (1, 1, 4, Some(0)), (1, 0, 6, Some(0))]
+        );
+        assert!(segments.iter().all(|s| alphabet.name(s.symbol) == "F"));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::gui::{PresetSelection, SpeciesConfig, TreeGenerationConfig};
+
+/// Number of states kept in the history, older ones are forgotten
+const MAX_ENTRIES: usize = 100;
+
+/// Linear undo and redo history of states, each labelled with the edit that led to it
+pub struct UndoHistory<T> {
+    entries: Vec<(String, T)>,
+    /// Index of the entry matching the current state
+    current: usize,
+}
+
+impl<T> UndoHistory<T> {
+    pub fn new(initial: T) -> Self {
+        Self {
+            entries: vec![("Initial state".to_string(), initial)],
+            current: 0,
+        }
+    }
+
+    pub fn current(&self) -> &T {
+        &self.entries[self.current].1
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    /// Labels of all entries from the oldest one
+    pub fn labels(&self) -> impl Iterator<Item = &str> {
+        self.entries.iter().map(|(label, _)| label.as_str())
+    }
+
+    /// Records the state following the current one, discarding the states that were undone
+    pub fn record(&mut self, label: String, state: T) {
+        self.entries.truncate(self.current + 1);
+        self.entries.push((label, state));
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+        self.current = self.entries.len() - 1;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.current > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.current + 1 < self.entries.len()
+    }
+
+    pub fn undo(&mut self) -> Option<&T> {
+        self.go_to(self.current.checked_sub(1)?)
+    }
+
+    pub fn redo(&mut self) -> Option<&T> {
+        self.go_to(self.current + 1)
+    }
+
+    /// Makes the entry at the given index current, keeping the entries after it for redo
+    pub fn go_to(&mut self, index: usize) -> Option<&T> {
+        if index >= self.entries.len() {
+            return None;
+        }
+        self.current = index;
+        Some(&self.entries[index].1)
+    }
+}
+
+/// Configuration covered by undo and redo
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigSnapshot {
+    pub species: Vec<SpeciesConfig>,
+    pub tree_generation_config: TreeGenerationConfig,
+}
+
+impl ConfigSnapshot {
+    /// Names the edit that turned the previous snapshot into this one, for the history list
+    #[allow(clippy::float_cmp)]
+    pub fn describe_edit(&self, previous: &ConfigSnapshot) -> String {
+        if self.species.len() > previous.species.len() {
+            return "Add species".to_string();
+        }
+        if self.species.len() < previous.species.len() {
+            return "Remove species".to_string();
+        }
+
+        let Some((species, old)) = self
+            .species
+            .iter()
+            .zip(&previous.species)
+            .find(|(species, old)| species != old)
+        else {
+            return "Placement".to_string();
+        };
+        let (lsystem, old_lsystem) = (&species.lsystem_config, &old.lsystem_config);
+        let edit = if species.preset_selection != old.preset_selection
+            && species.preset_selection != PresetSelection::Custom
+        {
+            format!("preset {:?}", species.preset_selection)
+        } else if lsystem.axiom != old_lsystem.axiom
+            || lsystem.production_rules != old_lsystem.production_rules
+        {
+            "rules".to_string()
+        } else if lsystem.n_iterations != old_lsystem.n_iterations {
+            "iterations".to_string()
+        } else if lsystem.angle != old_lsystem.angle {
+            "angle".to_string()
+        } else if lsystem.fractal_height != old_lsystem.fractal_height {
+            "height".to_string()
+        } else if lsystem.terminal_ages != old_lsystem.terminal_ages {
+            "terminal ages".to_string()
+        } else if species.model_selection != old.model_selection {
+            "base model".to_string()
+        } else if species.interpolation_color_low != old.interpolation_color_low
+            || species.interpolation_color_high != old.interpolation_color_high
+        {
+            "colors".to_string()
+        } else if species.name != old.name {
+            "name".to_string()
+        } else {
+            "frequency".to_string()
+        };
+        format!("{}: {edit}", species.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_undo_redo() {
+        let mut history = UndoHistory::new(0);
+        assert!(!history.can_undo());
+        history.record("one".to_string(), 1);
+        history.record("two".to_string(), 2);
+
+        assert_eq!(history.undo(), Some(&1));
+        assert_eq!(history.undo(), Some(&0));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo(), Some(&1));
+        assert!(history.can_redo());
+
+        // A new edit discards the undone states
+        history.record("three".to_string(), 3);
+        assert!(!history.can_redo());
+        assert_eq!(
+            history.labels().collect::<Vec<_>>(),
+            ["Initial state", "one", "three"]
+        );
+        assert_eq!(history.go_to(0), Some(&0));
+        assert_eq!(history.current_index(), 0);
+        assert_eq!(history.go_to(3), None);
+    }
+
+    #[test]
+    fn test_history_length() {
+        let mut history = UndoHistory::new(0);
+        for i in 1..=MAX_ENTRIES + 10 {
+            history.record(i.to_string(), i);
+        }
+        assert_eq!(history.labels().count(), MAX_ENTRIES);
+        assert_eq!(history.current(), &(MAX_ENTRIES + 10));
+        assert_eq!(history.go_to(0), Some(&11));
+    }
+
+    #[test]
+    fn test_edit_description() {
+        let snapshot = ConfigSnapshot {
+            species: vec![SpeciesConfig::default()],
+            tree_generation_config: TreeGenerationConfig::default(),
+        };
+
+        let mut edited = snapshot.clone();
+        edited.species[0].lsystem_config.production_rules.clear();
+        edited.species[0].preset_selection = PresetSelection::Custom;
+        assert_eq!(edited.describe_edit(&snapshot), "Tree: rules");
+
+        let mut edited = snapshot.clone();
+        edited.species[0].lsystem_config = PresetSelection::Bush.to_config();
+        edited.species[0].preset_selection = PresetSelection::Bush;
+        assert_eq!(edited.describe_edit(&snapshot), "Tree: preset Bush");
+
+        let mut edited = snapshot.clone();
+        edited.species.push(SpeciesConfig::default());
+        assert_eq!(edited.describe_edit(&snapshot), "Add species");
+        assert_eq!(snapshot.describe_edit(&edited), "Remove species");
+    }
+}
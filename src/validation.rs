@@ -0,0 +1,329 @@
+use crate::gui::LSystemConfig;
+use crate::symbol;
+use crate::turtle::TurtleInterpreter;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The plant is not regenerated until the problem is fixed
+    Error,
+    /// The plant can be generated, but likely not as intended
+    Warning,
+}
+
+/// Part of the L-system a diagnostic refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Axiom,
+    /// Predecessor of the rule at the given index
+    Predecessor(usize),
+    /// Successor of the rule at the given index
+    Successor(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: Location,
+    /// Index of the first character of the offending symbol within the axiom or rule,
+    /// if there is one
+    pub position: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Returns true if the diagnostic refers to the rule at the given index
+    pub fn is_for_rule(&self, rule_index: usize) -> bool {
+        matches!(
+            self.location,
+            Location::Predecessor(i) | Location::Successor(i) if i == rule_index
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let part = match self.location {
+            Location::Axiom => "axiom".to_string(),
+            Location::Predecessor(i) => format!("rule {i} predecessor"),
+            Location::Successor(i) => format!("rule {i} successor"),
+        };
+        match self.position {
+            Some(position) => write!(f, "{severity} in {part} at {position}: {}", self.message),
+            None => write!(f, "{severity} in {part}: {}", self.message),
+        }
+    }
+}
+
+/// Checks the axiom and production rules for problems, in the order they appear
+pub fn validate(config: &LSystemConfig) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let predecessors: Vec<&str> = config
+        .production_rules
+        .iter()
+        .map(|(predecessor, _)| symbol::name_of(predecessor))
+        .collect();
+
+    if config.axiom.is_empty() {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            location: Location::Axiom,
+            position: None,
+            message: "axiom is empty".to_string(),
+        });
+    }
+    check_word(
+        &config.axiom,
+        Location::Axiom,
+        &predecessors,
+        &mut diagnostics,
+    );
+
+    let mut first_definitions: HashMap<&str, usize> = HashMap::new();
+    for (rule_index, (predecessor, successor)) in config.production_rules.iter().enumerate() {
+        let location = Location::Predecessor(rule_index);
+        let mut error = |message: String| {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                location,
+                position: Some(0),
+                message,
+            });
+        };
+
+        let name = symbol::name_of(predecessor);
+        if name.trim().is_empty() {
+            error("predecessor is empty".to_string());
+        } else if symbol::tokenize(predecessor).len() > 1 {
+            error(format!(
+                "predecessor '{predecessor}' is not a single symbol, \
+                 write names of several characters in braces, like '{{{predecessor}}}'"
+            ));
+        } else if name.starts_with('{') {
+            error("unclosed '{'".to_string());
+        } else if matches!(name, "[" | "]") {
+            error(format!("branch symbol '{predecessor}' cannot be rewritten"));
+        } else if let Some(first) = first_definitions.get(name) {
+            error(format!(
+                "duplicate predecessor '{predecessor}', already rewritten by rule {first}"
+            ));
+        } else {
+            first_definitions.insert(name, rule_index);
+        }
+
+        check_word(
+            successor,
+            Location::Successor(rule_index),
+            &predecessors,
+            &mut diagnostics,
+        );
+    }
+
+    diagnostics
+}
+
+/// Checks bracket balance and symbols of an axiom or successor
+fn check_word(
+    word: &str,
+    location: Location,
+    predecessors: &[&str],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let mut open_brackets = Vec::new();
+    let mut reported_symbols = Vec::new();
+    let tokens = symbol::tokenize(word);
+    // Tokens before this index spell out a name reported as missing its braces
+    let mut spelled_until = 0;
+
+    for (index, &(position, symbol)) in tokens.iter().enumerate() {
+        let mut report = |severity, message| {
+            diagnostics.push(Diagnostic {
+                severity,
+                location,
+                position: Some(position),
+                message,
+            });
+        };
+
+        if index < spelled_until {
+            continue;
+        }
+        if let Some(name) = spelled_name(&tokens[index..], predecessors) {
+            spelled_until = index + name.chars().count();
+            report(
+                Severity::Warning,
+                format!("'{name}' is read as separate symbols, write '{{{name}}}' for the symbol"),
+            );
+            continue;
+        }
+
+        match symbol {
+            "[" => open_brackets.push(position),
+            "]" if open_brackets.pop().is_none() => {
+                report(Severity::Error, "unmatched ']'".to_string());
+            }
+            "}" => {
+                report(Severity::Error, "unmatched '}'".to_string());
+            }
+            _ if symbol.starts_with('{') => {
+                report(Severity::Error, "unclosed '{'".to_string());
+            }
+            _ if symbol.trim().is_empty() => {
+                report(Severity::Error, "empty symbol".to_string());
+            }
+            _ if !TurtleInterpreter::is_command(symbol)
+                && !predecessors.contains(&symbol)
+                && !reported_symbols.contains(&symbol) =>
+            {
+                reported_symbols.push(symbol);
+                report(
+                    Severity::Warning,
+                    format!("symbol '{symbol}' is neither drawn nor rewritten"),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    for position in open_brackets {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            location,
+            position: Some(position),
+            message: "unclosed '['".to_string(),
+        });
+    }
+}
+
+/// Returns the predecessor of several characters that the tokens start with, if it is
+/// written without braces and therefore read as one symbol per character
+fn spelled_name<'a>(tokens: &[(usize, &str)], predecessors: &[&'a str]) -> Option<&'a str> {
+    predecessors.iter().copied().find(|name| {
+        let length = name.chars().count();
+        length > 1
+            && tokens.len() >= length
+            && tokens[..length]
+                .iter()
+                .map(|(_, symbol)| *symbol)
+                .collect::<String>()
+                == *name
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gui::PresetSelection;
+
+    fn config(axiom: &str, rules: &[(&str, &str)]) -> LSystemConfig {
+        LSystemConfig {
+            axiom: axiom.to_string(),
+            production_rules: rules
+                .iter()
+                .map(|(predecessor, successor)| {
+                    ((*predecessor).to_string(), (*successor).to_string())
+                })
+                .collect(),
+            ..LSystemConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_valid_presets() {
+        for preset in [
+            PresetSelection::Tree3D,
+            PresetSelection::Bush,
+            PresetSelection::Seaweed,
+            PresetSelection::TrunkTree,
+        ] {
+            let diagnostics = validate(&preset.to_config());
+            assert!(
+                diagnostics.iter().all(|diagnostic| !diagnostic.is_error()),
+                "{preset:?}: {diagnostics:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_unbalanced_brackets() {
+        let diagnostics = validate(&config("F]", &[("F", "F[+F[-F]")]));
+        let errors: Vec<(Location, Option<usize>)> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.location, diagnostic.position))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (Location::Axiom, Some(1)),
+                (Location::Successor(0), Some(1))
+            ]
+        );
+        assert!(diagnostics.iter().all(Diagnostic::is_error));
+    }
+
+    #[test]
+    fn test_duplicate_predecessors() {
+        let diagnostics = validate(&config("F", &[("F", "FF"), ("X", "F"), ("F", "F")]));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].location, Location::Predecessor(2));
+        assert!(diagnostics[0].message.contains("rule 0"));
+    }
+
+    #[test]
+    fn test_empty_and_unknown_symbols() {
+        let diagnostics = validate(&config("", &[(" ", "F Y"), ("Y", "Z+Z")]));
+        let summary: Vec<(Severity, Location)> = diagnostics
+            .iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.location))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Severity::Error, Location::Axiom),
+                (Severity::Error, Location::Predecessor(0)),
+                (Severity::Error, Location::Successor(0)),
+                (Severity::Warning, Location::Successor(1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multi_character_symbols() {
+        let diagnostics = validate(&config("{Apex}", &[("{Apex}", "F[+{Leaf}]{Apex}")]));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].position, Some(3));
+        assert!(diagnostics[0].message.contains("'Leaf'"));
+
+        let diagnostics = validate(&config("F", &[("FX", "F")]));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].location, Location::Predecessor(0));
+        assert!(diagnostics[0].message.contains("'{FX}'"));
+    }
+
+    #[test]
+    fn test_names_without_braces() {
+        // 'Apex' in the successor is read as four symbols, reported once instead of per letter
+        let diagnostics = validate(&config("{Apex}", &[("{Apex}", "F[+F]Apex")]));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].position, Some(5));
+        assert!(diagnostics[0].message.contains("'{Apex}'"));
+
+        let diagnostics = validate(&config("F{Ap", &[("{F", "F}")]));
+        let messages: Vec<&str> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.message.as_str())
+            .collect();
+        assert_eq!(messages, ["unclosed '{'", "unclosed '{'", "unmatched '}'"]);
+    }
+}